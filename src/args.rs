@@ -0,0 +1,106 @@
+/*
+ * Command line helpers shared by the emulator and the chip8-asm and
+ * chip8-disasm tools. Not part of the library, every binary includes this
+ * file as a module of its own.
+ */
+
+// hex with a 0x prefix, decimal otherwise
+pub fn parse_number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(digits) => u64::from_str_radix(digits, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+pub fn parse_addr(value: &str) -> Option<u16> {
+    u16::try_from(parse_number(value)?).ok()
+}
+
+// Command line arguments where "--opt=value" is the same as "--opt value".
+// Iterating yields the arguments with the "=value" cut off, `value` then
+// gets the value of the option that came last.
+pub struct Args<I> {
+    args: I,
+    opt: String,
+    inline: Option<String>,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    pub fn new(args: I) -> Self {
+        Self { args, opt: String::new(), inline: None }
+    }
+
+    pub fn value(&mut self) -> Result<String, String> {
+        match self.inline.take().or_else(|| self.args.next()) {
+            Some(value) => Ok(value),
+            None => Err(format!("option {} needs a value", self.opt)),
+        }
+    }
+
+    // call once an option is handled, fails if it came with a "=value"
+    // that `value` didn't take
+    pub fn no_value(&mut self) -> Result<(), String> {
+        match self.inline.take() {
+            Some(_) => Err(format!("option {} doesn't take a value", self.opt)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for Args<I> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let arg = self.args.next()?;
+        let (opt, inline) = match arg.split_once('=') {
+            Some((opt, value)) if opt.starts_with("--") => (opt.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        self.opt = opt.clone();
+        self.inline = inline;
+        Some(opt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Args<std::vec::IntoIter<String>> {
+        let args: Vec<String> = line.split(' ').map(str::to_string).collect();
+        Args::new(args.into_iter())
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("0x1F"), Some(0x1f));
+        assert_eq!(parse_number("0X1f"), Some(0x1f));
+        assert_eq!(parse_number("31"), Some(31));
+        assert_eq!(parse_number("1f"), None);
+        assert_eq!(parse_addr("0xffff"), Some(0xffff));
+        assert_eq!(parse_addr("65536"), None);
+    }
+
+    #[test]
+    fn inline_values() {
+        let mut args = args("--scale=4 --fg 00ff00 rom=1.ch8 -o=x");
+        assert_eq!(args.next().as_deref(), Some("--scale"));
+        assert_eq!(args.value().as_deref(), Ok("4"));
+        assert_eq!(args.next().as_deref(), Some("--fg"));
+        assert_eq!(args.value().as_deref(), Ok("00ff00"));
+        assert_eq!(args.next().as_deref(), Some("rom=1.ch8"));
+        assert_eq!(args.no_value(), Ok(()));
+        assert_eq!(args.next().as_deref(), Some("-o=x"));
+        assert_eq!(args.value(), Err("option -o=x needs a value".to_string()));
+    }
+
+    #[test]
+    fn flags_take_no_value() {
+        let mut args = args("--mute=foo --headless");
+        args.next();
+        assert_eq!(args.no_value(), Err("option --mute doesn't take a value".to_string()));
+        args.next();
+        assert_eq!(args.no_value(), Ok(()));
+        assert_eq!(args.next(), None);
+    }
+}
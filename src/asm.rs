@@ -0,0 +1,653 @@
+/*
+ * Assembler for the mnemonics `Instruction` displays as (Cowgod's, plus the
+ * SUPER-CHIP and XO-CHIP ones), so disassembled programs assemble again.
+ *
+ *     ; comments run to the end of the line
+ *     SPEED EQU 3             ; constant, NAME = 3 works too
+ *     ORG 0x200               ; where the output starts, 0x200 by default
+ *     main:
+ *         LD V0, SPEED
+ *         LD I, sprite
+ *         DRW V0, V1, 4
+ *         JP main
+ *     sprite:
+ *         DB 0xF0, 0x90, 0b11110000, 144
+ *         DW 0x1234           ; big endian
+ *         DB "text"
+ *     INCLUDE "other.asm"     ; relative to the including file
+ *
+ * Operands are numbers (decimal, 0x hex, 0b binary), labels and constants,
+ * added and subtracted with + and -. Mnemonics and register names don't
+ * care about case, labels and constants do.
+ */
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::consts::{RAM_SIZE, START_ADDR};
+use crate::instruction::Instruction;
+
+// deepest INCLUDE nesting, stops files that include themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// what went wrong and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Assembles `source`, includes are looked up relative to the working
+// directory. Returns the bytes to load at the first ORG, 0x200 by default.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new();
+    asm.read("<source>", source, Path::new("."), 0)?;
+    asm.finish()
+}
+
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::new();
+    asm.read_file(path, None, 0)?;
+    asm.finish()
+}
+
+// Assembles source lines into a program, panicking with the error message
+// if they don't assemble:
+//
+//     let rom = chip8_asm!["LD V0, 1", "loop:", "JP loop"];
+#[macro_export]
+macro_rules! chip8_asm {
+    ($($line:expr),* $(,)?) => {
+        match $crate::asm::assemble(&[$($line),*].join("\n")) {
+            Ok(bytes) => bytes,
+            Err(e) => panic!("{}", e),
+        }
+    };
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(String, Vec<String>),
+    Db(Vec<String>),
+    Dw(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    Label(u16),
+    // evaluated when used, so constants can refer to later labels
+    Constant(String, Location),
+}
+
+struct Assembler {
+    // where the output starts, set by an ORG in front of everything else
+    origin: u16,
+    addr: u32,
+    statements: Vec<(u16, Statement, Location)>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            origin: START_ADDR,
+            addr: START_ADDR as u32,
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn read_file(&mut self, path: &Path, from: Option<&Location>, depth: usize)
+        -> Result<(), AsmError>
+    {
+        let name = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|e| match from {
+            Some(loc) => loc.error(format!("can't include {}: {}", name, e)),
+            None => AsmError { file: name.clone(), line: 0, message: e.to_string() },
+        })?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        self.read(&name, &source, dir, depth)
+    }
+
+    // first pass: collects statements and symbols
+    fn read(&mut self, name: &str, source: &str, dir: &Path, depth: usize)
+        -> Result<(), AsmError>
+    {
+        for (n, line) in source.lines().enumerate() {
+            let loc = Location { file: name.to_string(), line: n + 1 };
+            let mut line = strip_comment(line).trim();
+
+            // any number of labels in front of the statement
+            while let Some((label, rest)) = split_label(line) {
+                self.define(label, Symbol::Label(self.addr()), &loc)?;
+                line = rest.trim();
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            let (word, rest) = split_word(line);
+            let rest = rest.trim();
+
+            // NAME EQU value / NAME = value
+            let (second, value) = split_word(rest);
+            if second.eq_ignore_ascii_case("EQU") || second == "=" {
+                check_name(word, &loc)?;
+                self.define(word, Symbol::Constant(value.trim().to_string(), loc.clone()), &loc)?;
+                continue;
+            }
+            if let Some(value) = rest.strip_prefix('=') {
+                check_name(word, &loc)?;
+                self.define(word, Symbol::Constant(value.trim().to_string(), loc.clone()), &loc)?;
+                continue;
+            }
+
+            let operands = split_operands(rest, &loc)?;
+            let addr = self.addr();
+            match word.to_ascii_uppercase().as_str() {
+                "ORG" => {
+                    let [value] = operands.as_slice() else {
+                        return Err(loc.error("ORG takes one address"));
+                    };
+                    let target = self.eval(value, &loc, 0)?;
+                    if !(0..RAM_SIZE as i64).contains(&target) {
+                        return Err(loc.error(format!("ORG 0x{:x} is outside of memory", target)));
+                    }
+                    if self.statements.is_empty() {
+                        // labels in front of the first statement move along
+                        for symbol in self.symbols.values_mut() {
+                            if let Symbol::Label(at) = symbol {
+                                *at = target as u16;
+                            }
+                        }
+                        self.origin = target as u16;
+                    } else if (target as u32) < self.addr {
+                        return Err(loc.error(format!(
+                            "ORG 0x{:x} is behind the current address 0x{:x}",
+                            target, self.addr)));
+                    }
+                    self.addr = target as u32;
+                },
+                "INCLUDE" => {
+                    let [file] = operands.as_slice() else {
+                        return Err(loc.error("INCLUDE takes one file name"));
+                    };
+                    let file = match parse_string(file) {
+                        Some(file) => file,
+                        None => return Err(loc.error("INCLUDE needs a quoted file name")),
+                    };
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(loc.error("INCLUDE nested too deep"));
+                    }
+                    let path: PathBuf = dir.join(file);
+                    self.read_file(&path, Some(&loc), depth + 1)?;
+                },
+                "DB" => {
+                    let mut size = 0;
+                    for operand in operands.iter() {
+                        size += match parse_string(operand) {
+                            Some(text) => text.len() as u32,
+                            None => 1,
+                        };
+                    }
+                    self.push(addr, size, Statement::Db(operands), loc)?;
+                },
+                "DW" => {
+                    let size = operands.len() as u32 * 2;
+                    self.push(addr, size, Statement::Dw(operands), loc)?;
+                },
+                mnemonic => {
+                    let long = mnemonic == "LD" && operands.get(1)
+                        .is_some_and(|o| o.to_ascii_uppercase().starts_with("LONG "));
+                    let size = if long { 4 } else { 2 };
+                    self.push(addr, size, Statement::Instruction(mnemonic.to_string(), operands), loc)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn addr(&self) -> u16 {
+        self.addr as u16
+    }
+
+    fn push(&mut self, addr: u16, size: u32, statement: Statement, loc: Location)
+        -> Result<(), AsmError>
+    {
+        if self.addr + size > RAM_SIZE as u32 {
+            return Err(loc.error("program doesn't fit in memory"));
+        }
+        self.addr += size;
+        self.statements.push((addr, statement, loc));
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, loc: &Location) -> Result<(), AsmError> {
+        if self.symbols.contains_key(name) {
+            return Err(loc.error(format!("{} is already defined", name)));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    // second pass: now every label is known
+    fn finish(self) -> Result<Vec<u8>, AsmError> {
+        let origin = self.origin;
+        let mut out = Vec::new();
+        for (addr, statement, loc) in self.statements.iter() {
+            // ORG gaps are filled with zeros
+            out.resize((*addr - origin) as usize, 0);
+            match statement {
+                Statement::Db(operands) => for operand in operands {
+                    match parse_string(operand) {
+                        Some(text) => out.extend_from_slice(text.as_bytes()),
+                        None => out.push(self.byte(operand, loc)?),
+                    }
+                },
+                Statement::Dw(operands) => for operand in operands {
+                    let word = self.ranged(operand, loc, -0x8000, 0xffff)?;
+                    out.extend_from_slice(&(word as u16).to_be_bytes());
+                },
+                Statement::Instruction(mnemonic, operands) => {
+                    let instruction = self.instruction(mnemonic, operands, loc)?;
+                    out.extend_from_slice(&instruction.to_bytes());
+                },
+            }
+        }
+        Ok(out)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[String], loc: &Location)
+        -> Result<Instruction, AsmError>
+    {
+        use Operand::*;
+
+        let ops: Vec<Operand> = operands.iter().map(|o| Operand::parse(o)).collect();
+        let addr = |e: &str| self.ranged(e, loc, 0, 0xfff).map(|v| v as u16);
+        let byte = |e: &str| self.byte(e, loc);
+        let nibble = |e: &str| self.ranged(e, loc, 0, 0xf).map(|v| v as u8);
+
+        let instruction = match (mnemonic, ops.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Expr(n)]) => Instruction::ScrollDown(nibble(n)?),
+            ("SCU", [Expr(n)]) => Instruction::ScrollUp(nibble(n)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::LoRes,
+            ("HIGH", []) => Instruction::HiRes,
+            ("SYS", [Expr(nnn)]) => Instruction::Sys(addr(nnn)?),
+            ("JP", [Expr(nnn)]) => Instruction::Jump(addr(nnn)?),
+            ("JP", [V(0), Expr(nnn)]) => {
+                let nnn = addr(nnn)?;
+                Instruction::JumpOffset((nnn >> 8) as usize, nnn)
+            },
+            ("CALL", [Expr(nnn)]) => Instruction::Call(addr(nnn)?),
+            ("SE", [V(x), Expr(kk)]) => Instruction::SkipEqByte(*x, byte(kk)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipEqReg(*x, *y),
+            ("SNE", [V(x), Expr(kk)]) => Instruction::SkipNeByte(*x, byte(kk)?),
+            ("SNE", [V(x), V(y)]) => Instruction::SkipNeReg(*x, *y),
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange(*x, *y),
+            ("LD", [V(x), Expr(kk)]) => Instruction::LoadByte(*x, byte(kk)?),
+            ("LD", [V(x), V(y)]) => Instruction::Move(*x, *y),
+            ("LD", [I, Long(nnnn)]) =>
+                Instruction::LoadILong(self.ranged(nnnn, loc, 0, 0xffff)? as u16),
+            ("LD", [I, Expr(nnn)]) => Instruction::LoadI(addr(nnn)?),
+            ("LD", [V(x), Dt]) => Instruction::LoadDelay(*x),
+            ("LD", [V(x), K]) => Instruction::WaitKey(*x),
+            ("LD", [Dt, V(x)]) => Instruction::SetDelay(*x),
+            ("LD", [St, V(x)]) => Instruction::SetSound(*x),
+            ("LD", [F, V(x)]) => Instruction::Font(*x),
+            ("LD", [Hf, V(x)]) => Instruction::BigFont(*x),
+            ("LD", [B, V(x)]) => Instruction::Bcd(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::Store(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::Load(*x),
+            ("LD", [R, V(x)]) => Instruction::SaveFlags(*x),
+            ("LD", [V(x), R]) => Instruction::LoadFlags(*x),
+            ("ADD", [V(x), Expr(kk)]) => Instruction::AddByte(*x, byte(kk)?),
+            ("ADD", [V(x), V(y)]) => Instruction::Add(*x, *y),
+            ("ADD", [I, V(x)]) => Instruction::AddI(*x),
+            ("OR", [V(x), V(y)]) => Instruction::Or(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::And(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::SubN(*x, *y),
+            ("SHR", [V(x), V(y)]) => Instruction::Shr(*x, *y),
+            ("SHR", [V(x)]) => Instruction::Shr(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::Shl(*x, *y),
+            ("SHL", [V(x)]) => Instruction::Shl(*x, *x),
+            ("RND", [V(x), Expr(kk)]) => Instruction::Random(*x, byte(kk)?),
+            ("DRW", [V(x), V(y), Expr(n)]) => Instruction::Draw(*x, *y, nibble(n)?),
+            ("SKP", [V(x)]) => Instruction::SkipKey(*x),
+            ("SKNP", [V(x)]) => Instruction::SkipNotKey(*x),
+            ("PLANE", [Expr(n)]) => Instruction::Plane(nibble(n)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [V(x)]) => Instruction::Pitch(*x),
+            _ if !MNEMONICS.contains(&mnemonic) =>
+                return Err(loc.error(format!("unknown mnemonic {}", mnemonic))),
+            _ => return Err(loc.error(format!("invalid operands for {}: {}",
+                mnemonic, operands.join(", ")))),
+        };
+        Ok(instruction)
+    }
+
+    fn byte(&self, expr: &str, loc: &Location) -> Result<u8, AsmError> {
+        // negative bytes are two's complement, handy for ADD Vx, -1
+        self.ranged(expr, loc, -0x80, 0xff).map(|v| v as u8)
+    }
+
+    fn ranged(&self, expr: &str, loc: &Location, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self.eval(expr, loc, 0)?;
+        if value < min || value > max {
+            let shown = if expr == value.to_string() {
+                expr.to_string()
+            } else {
+                format!("{} = {}", expr, value)
+            };
+            return Err(loc.error(format!("{} doesn't fit in {}..={}", shown, min, max)));
+        }
+        Ok(value)
+    }
+
+    // terms added and subtracted left to right
+    fn eval(&self, expr: &str, loc: &Location, depth: usize) -> Result<i64, AsmError> {
+        if depth > self.symbols.len() {
+            return Err(loc.error(format!("{} refers to itself", expr)));
+        }
+        let expr = expr.trim();
+        if expr.is_empty() {
+            return Err(loc.error("missing value"));
+        }
+
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut rest = expr;
+        loop {
+            rest = rest.trim_start();
+            if let Some(r) = rest.strip_prefix('-') {
+                sign = -sign;
+                rest = r;
+                continue;
+            }
+            if let Some(r) = rest.strip_prefix('+') {
+                rest = r;
+                continue;
+            }
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = rest[..end].trim();
+            if term.is_empty() {
+                return Err(loc.error(format!("missing value in {}", expr)));
+            }
+            total += sign * self.term(term, loc, depth)?;
+            rest = &rest[end..];
+            if rest.is_empty() {
+                return Ok(total);
+            }
+            sign = 1;
+        }
+    }
+
+    fn term(&self, term: &str, loc: &Location, depth: usize) -> Result<i64, AsmError> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        match self.symbols.get(term) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Constant(expr, at)) => self.eval(expr, at, depth + 1)
+                .map_err(|e| loc.error(format!("in {}: {}", term, e))),
+            None if term.chars().next().is_some_and(|c| c.is_ascii_digit()) =>
+                Err(loc.error(format!("invalid number {}", term))),
+            None => Err(loc.error(format!("undefined symbol {}", term))),
+        }
+    }
+}
+
+const MNEMONICS: [&str; 32] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS",
+    "JP", "CALL", "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND",
+    "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP",
+    "PLANE", "AUDIO", "PITCH",
+];
+
+enum Operand<'a> {
+    V(usize),
+    I,
+    // [I]
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    // LONG nnnn
+    Long(&'a str),
+    Expr(&'a str),
+}
+
+impl<'a> Operand<'a> {
+    fn parse(operand: &'a str) -> Operand<'a> {
+        let upper = operand.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => return Operand::I,
+            "[I]" => return Operand::IndirectI,
+            "DT" => return Operand::Dt,
+            "ST" => return Operand::St,
+            "K" => return Operand::K,
+            "F" => return Operand::F,
+            "HF" => return Operand::Hf,
+            "B" => return Operand::B,
+            "R" => return Operand::R,
+            _ => (),
+        }
+        if upper.starts_with("LONG ") {
+            return Operand::Long(operand[5..].trim());
+        }
+        let mut chars = upper.chars();
+        if let (Some('V'), Some(x), None) = (chars.next(), chars.next(), chars.next()) {
+            if let Some(x) = x.to_digit(16) {
+                return Operand::V(x as usize);
+            }
+        }
+        Operand::Expr(operand)
+    }
+}
+
+fn parse_number(term: &str) -> Option<i64> {
+    let lower = term.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+// "text" without the quotes
+fn parse_string(operand: &str) -> Option<&str> {
+    operand.strip_prefix('"')?.strip_suffix('"')
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+// "name: rest" -> ("name", "rest")
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let (label, rest) = line.split_once(':')?;
+    if is_name(label) {
+        Some((label, rest))
+    } else {
+        None
+    }
+}
+
+fn split_word(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], &line[end..]),
+        None => (line, ""),
+    }
+}
+
+fn split_operands(operands: &str, loc: &Location) -> Result<Vec<String>, AsmError> {
+    if operands.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in operands.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            },
+            ',' if !quoted => out.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    if quoted {
+        return Err(loc.error("unterminated string"));
+    }
+    out.push(current);
+
+    let out: Vec<String> = out.into_iter().map(|o| o.trim().to_string()).collect();
+    if out.iter().any(|o| o.is_empty()) {
+        return Err(loc.error("empty operand"));
+    }
+    Ok(out)
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn check_name(name: &str, loc: &Location) -> Result<(), AsmError> {
+    if is_name(name) {
+        Ok(())
+    } else {
+        Err(loc.error(format!("{} is not a valid name", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::{self, Syntax};
+
+    #[test]
+    fn every_opcode_assembles_from_its_mnemonic() {
+        for op in 0..=0xffff {
+            let instruction = match Instruction::decode(op) {
+                Ok(instruction) => instruction,
+                Err(_) => continue,
+            };
+            let rom = chip8_asm![instruction.to_string()];
+            assert_eq!(rom, op.to_be_bytes(), "{}", instruction);
+        }
+        assert_eq!(chip8_asm!["LD I, LONG 0x1234"], [0xf0, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let rom = chip8_asm![
+            "SPEED EQU 3",
+            "main:",
+            "    LD V0, SPEED + 1",
+            "    LD I, sprite",
+            "    JP main",
+            "sprite:",
+            "    DB 0xF0, 0b1001, 144",
+            "    DW 0x1234",
+            "    DB \"ok\"",
+        ];
+        assert_eq!(rom, [0x60, 0x04, 0xa2, 0x06, 0x12, 0x00,
+            0xf0, 0x09, 0x90, 0x12, 0x34, b'o', b'k']);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let e = assemble("CLS\n\nFOO V1\n").unwrap_err();
+        assert_eq!((e.file.as_str(), e.line), ("<source>", 3));
+        assert_eq!(e.message, "unknown mnemonic FOO");
+
+        let e = assemble("main:\n  JP nowhere\n").unwrap_err();
+        assert_eq!(e.line, 2);
+        assert_eq!(e.message, "undefined symbol nowhere");
+
+        let e = assemble("LD V0, 1\nLD V0, 256\n").unwrap_err();
+        assert_eq!(e.line, 2);
+
+        let e = assemble("a:\na:\n").unwrap_err();
+        assert_eq!(e.to_string(), "<source>:2: a is already defined");
+    }
+
+    #[test]
+    fn disassembly_assembles_again() {
+        let rom = chip8_asm![
+            "ORG 0x300",
+            "    LD V0, 0",
+            "    LD I, sprite",
+            "loop:",
+            "    DRW V0, V0, 5",
+            "    CALL step",
+            "    SE V0, 60",
+            "    JP loop",
+            "    LD I, LONG sprite",
+            "    EXIT",
+            "step:",
+            "    ADD V0, 1",
+            "    RET",
+            "sprite:",
+            "    DB 0xF0, 0x90, 0x90, 0x90, 0xF0, 0x00, 0xFF",
+        ];
+        for listing in [true, false] {
+            let text = disasm::disassemble(&rom, 0x300).format(Syntax::Cowgod, listing);
+            assert_eq!(assemble(&text), Ok(rom.clone()), "{}", text);
+        }
+    }
+}
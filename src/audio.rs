@@ -0,0 +1,180 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+use chip_8::consts::AUDIO_PATTERN_SIZE;
+use chip_8::frontend::AudioSink;
+
+const SAMPLE_RATE: i32 = 44100;
+// how long the volume takes to fade in and out, avoids clicks
+const RAMP_SECONDS: f32 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("unknown waveform: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AudioConfig {
+    pub waveform: Waveform,
+    // tone frequency in Hz
+    pub frequency: f32,
+    // 0.0 - 1.0
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Square,
+            frequency: 440.0,
+            volume: 0.25,
+            muted: false,
+        }
+    }
+}
+
+struct Tone {
+    waveform: Waveform,
+    // position within the current period, 0.0 - 1.0
+    phase: f32,
+    phase_inc: f32,
+    // XO-CHIP pattern played instead of the waveform, with the bits it
+    // moves on per sample, and the bit it is at
+    pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>,
+    bit: f32,
+    sample_rate: f32,
+    // current and wanted amplitude, the first follows the second
+    amplitude: f32,
+    target: f32,
+    ramp_step: f32,
+}
+
+impl Tone {
+    fn sample(&self) -> f32 {
+        if let Some((pattern, _)) = &self.pattern {
+            let bit = self.bit as usize;
+            return if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 };
+        }
+        match self.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+        }
+    }
+}
+
+impl AudioCallback for Tone {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            if self.amplitude < self.target {
+                self.amplitude = (self.amplitude + self.ramp_step).min(self.target);
+            } else if self.amplitude > self.target {
+                self.amplitude = (self.amplitude - self.ramp_step).max(self.target);
+            }
+            *x = self.sample() * self.amplitude;
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+            if let Some((_, step)) = self.pattern {
+                self.bit = (self.bit + step) % (AUDIO_PATTERN_SIZE * 8) as f32;
+            }
+        }
+    }
+}
+
+// Plays a tone, or on XO-CHIP the program's pattern, while the sound
+// timer is running.
+pub struct Beeper {
+    device: AudioDevice<Tone>,
+    volume: f32,
+    active: bool,
+    muted: bool,
+    pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>,
+}
+
+impl Beeper {
+    pub fn new(ctx: &sdl2::Sdl, cfg: &AudioConfig) -> Result<Self, String> {
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let volume = cfg.volume.clamp(0.0, 1.0);
+        let device = ctx.audio()?.open_playback(None, &spec, |spec| {
+            Tone {
+                waveform: cfg.waveform,
+                phase: 0.0,
+                phase_inc: cfg.frequency / spec.freq as f32,
+                pattern: None,
+                bit: 0.0,
+                sample_rate: spec.freq as f32,
+                amplitude: 0.0,
+                target: 0.0,
+                ramp_step: volume / (RAMP_SECONDS * spec.freq as f32),
+            }
+        })?;
+        // the device keeps running, silence is just a zero target amplitude
+        device.resume();
+
+        Ok(Self {
+            device,
+            volume,
+            active: false,
+            muted: cfg.muted,
+            pattern: None,
+        })
+    }
+
+    fn update(&mut self) {
+        let target = if self.active && !self.muted { self.volume } else { 0.0 };
+        self.device.lock().target = target;
+    }
+}
+
+impl AudioSink for Beeper {
+    fn set_active(&mut self, active: bool) {
+        if active != self.active {
+            self.active = active;
+            self.update();
+        }
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.update();
+    }
+
+    fn muted(&self) -> bool {
+        self.muted
+    }
+
+    fn set_pattern(&mut self, pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>) {
+        if pattern == self.pattern {
+            return;
+        }
+        self.pattern = pattern;
+        let mut tone = self.device.lock();
+        tone.pattern = pattern.map(|(bits, rate)| (bits, rate / tone.sample_rate));
+        if pattern.is_none() {
+            tone.bit = 0.0;
+        }
+    }
+}
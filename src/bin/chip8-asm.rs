@@ -0,0 +1,72 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use chip_8::asm;
+
+// shared with the emulator, the library has no command line code
+#[allow(dead_code)]
+#[path = "../args.rs"]
+mod args;
+
+use args::Args;
+
+fn usage(prog_name: &str) -> String {
+    format!("\
+usage: {} [options] source.asm
+
+options:
+  -o FILE      write the program to FILE (default: source with .ch8)
+  -h, --help   print this help and exit
+",
+        prog_name)
+}
+
+fn main() {
+    let mut args = Args::new(env::args());
+    let prog_name = args.next().unwrap_or_else(|| "chip8-asm".to_string());
+
+    let mut output = None;
+    let mut input = None;
+
+    let fail = |e: String| -> ! {
+        eprintln!("{}: {}", prog_name, e);
+        eprintln!("Try '{} --help' for more information.", prog_name);
+        process::exit(2);
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.value().unwrap_or_else(|e| fail(e));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", usage(&prog_name));
+                return;
+            },
+            "-o" => output = Some(PathBuf::from(value())),
+            _ if arg.starts_with('-') && arg.len() > 1 =>
+                fail(format!("unknown option {}", arg)),
+            _ if input.is_some() => fail(format!("unexpected argument {}", arg)),
+            _ => input = Some(PathBuf::from(arg)),
+        }
+        args.no_value().unwrap_or_else(|e| fail(e));
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None => fail("no source file given".to_string()),
+    };
+    let output = output.unwrap_or_else(|| input.with_extension("ch8"));
+
+    let program = match asm::assemble_file(&input) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    };
+    if let Err(e) = fs::write(&output, &program) {
+        eprintln!("{}: can't write {}: {}", prog_name, output.display(), e);
+        process::exit(1);
+    }
+}
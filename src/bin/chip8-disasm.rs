@@ -0,0 +1,94 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip_8::consts::{RAM_SIZE, START_ADDR};
+use chip_8::disasm::{self, Syntax};
+
+// shared with the emulator, the library has no command line code
+#[path = "../args.rs"]
+mod args;
+
+use args::{parse_addr, Args};
+
+fn usage(prog_name: &str) -> String {
+    format!("\
+usage: {} [options] chip-8-filename.ch8
+
+options:
+  --syntax octo|cowgod   assembly syntax (default octo)
+  --load-addr ADDR       address the ROM is loaded at (default 0x{:03x})
+  --no-listing           leave out the address and raw bytes comments
+  -o FILE                write to FILE instead of standard output
+  -h, --help             print this help and exit
+",
+        prog_name, START_ADDR)
+}
+
+fn main() {
+    let mut args = Args::new(env::args());
+    let prog_name = args.next().unwrap_or_else(|| "chip8-disasm".to_string());
+
+    let mut syntax = Syntax::Octo;
+    let mut load_addr = START_ADDR;
+    let mut listing = true;
+    let mut output = None;
+    let mut input = None;
+
+    let fail = |e: String| -> ! {
+        eprintln!("{}: {}", prog_name, e);
+        eprintln!("Try '{} --help' for more information.", prog_name);
+        process::exit(2);
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.value().unwrap_or_else(|e| fail(e));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", usage(&prog_name));
+                return;
+            },
+            "--syntax" => syntax = value().parse().unwrap_or_else(|e| fail(e)),
+            "--load-addr" => {
+                let addr = value();
+                load_addr = match parse_addr(&addr) {
+                    Some(addr) => addr,
+                    None => fail(format!("invalid address {} for --load-addr", addr)),
+                };
+            },
+            "--no-listing" => listing = false,
+            "-o" => output = Some(value()),
+            _ if arg.starts_with('-') && arg.len() > 1 =>
+                fail(format!("unknown option {}", arg)),
+            _ if input.is_some() => fail(format!("unexpected argument {}", arg)),
+            _ => input = Some(arg),
+        }
+        args.no_value().unwrap_or_else(|e| fail(e));
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None => fail("no ROM given".to_string()),
+    };
+    let rom = match fs::read(&input) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}: can't read {}: {}", prog_name, input, e);
+            process::exit(1);
+        },
+    };
+    if rom.len() > RAM_SIZE - load_addr as usize {
+        eprintln!("{}: {} doesn't fit in memory at 0x{:03x}", prog_name, input, load_addr);
+        process::exit(1);
+    }
+
+    let text = disasm::disassemble(&rom, load_addr).format(syntax, listing);
+    match output {
+        Some(path) => if let Err(e) = fs::write(&path, text) {
+            eprintln!("{}: can't write {}: {}", prog_name, path, e);
+            process::exit(1);
+        },
+        None => print!("{}", text),
+    }
+}
+
@@ -0,0 +1,317 @@
+use std::str::FromStr;
+
+use chip_8::consts::*;
+use chip_8::instruction::OpClass;
+use chip_8::quirks::Quirks;
+use chip_8::trace::{TraceFilter, TraceFormat};
+
+use crate::args::{parse_addr, Args};
+use crate::audio::AudioConfig;
+
+pub const DEFAULT_SCALE: u32 = 10;
+pub const DEFAULT_FG: (u8, u8, u8) = (255, 255, 255);
+pub const DEFAULT_BG: (u8, u8, u8) = (0, 0, 0);
+pub const DEFAULT_TRACE_LIMIT_MB: u64 = 64;
+
+// what the command line asks for
+#[derive(Debug)]
+pub enum Command {
+    Run(Box<Config>),
+    Help,
+    Version,
+}
+
+// Options the ROM database can fill in are None unless given.
+#[derive(Debug)]
+pub struct Config {
+    pub chip8_filepath: String,
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    pub seed: Option<u64>,
+    pub load_addr: Option<u16>,
+    pub rewind_seconds: u32,
+    pub audio: AudioConfig,
+    // size of a lo-res pixel in window pixels, odd sizes are rounded up
+    // so hi-res pixels are exactly half of it
+    pub scale: u32,
+    pub fg: Option<(u8, u8, u8)>,
+    pub bg: Option<(u8, u8, u8)>,
+    pub fullscreen: bool,
+    // no window, sound or input, and no waiting for the next frame
+    pub headless: bool,
+    // quit after this many frames
+    pub frames: Option<u64>,
+    // where to find the ROM database instead of the built in copy
+    pub rom_db: Option<String>,
+    // stop in the debugger console before the first instruction
+    pub debug: bool,
+    // where to wait for a GDB connection, see gdb::listen
+    pub gdb: Option<String>,
+    // instruction trace file, see trace.rs
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub trace_limit_mb: u64,
+    // profile reports written when the run ends, see profile.rs
+    pub profile: Option<String>,
+    pub profile_json: Option<String>,
+    // annotated disassembly written when the run ends, see coverage.rs
+    pub coverage: Option<String>,
+    // warn about suspicious memory accesses and control flow, see sanitize.rs
+    pub sanitize: bool,
+}
+
+pub fn usage(prog_name: &str) -> String {
+    format!("\
+usage: {} [options] chip-8-filename.ch8
+
+options:
+  --scale N            size of a lo-res pixel in window pixels, rounded up to
+                       an even number (default {})
+  --fullscreen         fill the screen instead of opening a window
+  --fg RRGGBB          foreground colour (default ffffff)
+  --bg RRGGBB          background colour (default 000000)
+  --ips N              instructions per second (default {})
+  --ipf N              instructions per frame, instead of --ips
+  --quirks PRESET      {} (default modern)
+  --rom-db DIR         directory with the CHIP-8 database's programs.json
+                       and sha1-hashes.json (default
+                       $XDG_DATA_HOME/chip_8/chip-8-database), for the
+                       defaults of --quirks, --ips, --load-addr and the
+                       colours
+  --seed N             seed for the random number generator
+  --load-addr ADDR     address the ROM is loaded and started at (default 0x{:03x})
+  --rewind SECONDS     how far back Backspace can rewind, up to {} (default {})
+  --mute               start with the sound off
+  --waveform WAVE      square|sine|triangle (default square)
+  --tone HZ            beeper frequency (default 440)
+  --volume 0-100       beeper volume (default 25)
+  --headless           run without window, sound or input, as fast as possible
+  --frames N           quit after N frames
+  --debug              start in the debugger console, F11 stops in it later
+  --gdb PORT|unix:PATH wait for GDB to connect and run the ROM under it,
+                       PORT can also be HOST:PORT
+  --trace FILE         write every executed instruction to FILE
+  --trace-format FMT   text|binary (default text)
+  --trace-range A-B    only trace instructions at addresses A to B
+  --trace-class LIST   only trace these comma separated classes:
+                       {}
+  --trace-limit MB     stop tracing at this file size (default {})
+  --profile FILE       write an execution profile to FILE when the run ends
+  --profile-json FILE  the same as JSON
+  --coverage FILE      write the disassembly annotated with which instructions
+                       and skips ran to FILE when the run ends, and print a
+                       coverage summary
+  --sanitize           warn about reads of unwritten memory, writes to code
+                       or the font, executing data and similar bugs, with a
+                       summary when the run ends
+  -h, --help           print this help and exit
+  -V, --version        print the version and exit
+",
+        prog_name,
+        DEFAULT_SCALE,
+        DEFAULT_INSTRUCTIONS_PER_FRAME * FRAME_RATE,
+        Quirks::PRESET_NAMES.join("|"),
+        START_ADDR,
+        MAX_REWIND_SECONDS,
+        DEFAULT_REWIND_SECONDS,
+        OpClass::ALL.map(|class| class.name()).join(","),
+        DEFAULT_TRACE_LIMIT_MB)
+}
+
+// Errors are one line messages meant to be printed after the program name.
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = Args::new(args);
+    args.next();
+
+    let mut chip8_file = None;
+    let mut quirks = None;
+    let mut instructions_per_frame = None;
+    let mut seed = None;
+    let mut load_addr = None;
+    let mut rewind_seconds = DEFAULT_REWIND_SECONDS;
+    let mut audio = AudioConfig::default();
+    let mut scale = DEFAULT_SCALE;
+    let mut fg = None;
+    let mut bg = None;
+    let mut fullscreen = false;
+    let mut headless = false;
+    let mut frames = None;
+    let mut rom_db = None;
+    let mut debug = false;
+    let mut gdb = None;
+    let mut trace = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::default();
+    let mut trace_limit_mb = DEFAULT_TRACE_LIMIT_MB;
+    let mut profile = None;
+    let mut profile_json = None;
+    let mut coverage = None;
+    let mut sanitize = false;
+
+    while let Some(opt) = args.next() {
+        let mut value = || args.value();
+
+        match opt.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--scale" => {
+                scale = parse(&opt, &value()?)?;
+                if scale == 0 {
+                    return Err("--scale must be at least 1".to_string());
+                }
+            },
+            "--fullscreen" => fullscreen = true,
+            "--fg" => fg = Some(parse_colour(&opt, &value()?)?),
+            "--bg" => bg = Some(parse_colour(&opt, &value()?)?),
+            "--ips" => {
+                let ips: u32 = parse(&opt, &value()?)?;
+                if ips < FRAME_RATE {
+                    return Err(format!("--ips must be at least {}", FRAME_RATE));
+                }
+                instructions_per_frame = Some((ips + FRAME_RATE / 2) / FRAME_RATE);
+            },
+            "--ipf" => {
+                let ipf = parse(&opt, &value()?)?;
+                if ipf == 0 {
+                    return Err("--ipf must be at least 1".to_string());
+                }
+                instructions_per_frame = Some(ipf);
+            },
+            "--quirks" => {
+                let name = value()?;
+                quirks = match Quirks::from_name(&name) {
+                    Some(q) => Some(q),
+                    None => return Err(format!("unknown quirks preset {}, expected one of {}",
+                        name, Quirks::PRESET_NAMES.join(", "))),
+                };
+            },
+            "--seed" => seed = Some(parse(&opt, &value()?)?),
+            "--load-addr" => {
+                let addr = value()?;
+                load_addr = match parse_addr(&addr) {
+                    Some(addr) => Some(addr),
+                    None => return Err(format!("invalid address {} for --load-addr", addr)),
+                };
+            },
+            "--rewind" => {
+                rewind_seconds = parse(&opt, &value()?)?;
+                if rewind_seconds > MAX_REWIND_SECONDS {
+                    return Err(format!("--rewind must be between 0 and {}", MAX_REWIND_SECONDS));
+                }
+            },
+            "--mute" => audio.muted = true,
+            "--waveform" => audio.waveform = value()?.parse()?,
+            "--tone" => {
+                audio.frequency = parse(&opt, &value()?)?;
+                if audio.frequency.is_nan() || audio.frequency <= 0.0 {
+                    return Err("--tone must be above 0".to_string());
+                }
+            },
+            "--volume" => {
+                let volume: u8 = parse(&opt, &value()?)?;
+                if volume > 100 {
+                    return Err("--volume must be between 0 and 100".to_string());
+                }
+                audio.volume = volume as f32 / 100.0;
+            },
+            "--rom-db" => rom_db = Some(value()?),
+            "--headless" => headless = true,
+            "--frames" => {
+                let n = parse(&opt, &value()?)?;
+                if n == 0 {
+                    return Err("--frames must be at least 1".to_string());
+                }
+                frames = Some(n);
+            },
+            "--debug" => debug = true,
+            "--gdb" => gdb = Some(value()?),
+            "--trace" => trace = Some(value()?),
+            "--trace-format" => trace_format = value()?.parse()?,
+            "--trace-range" => {
+                let range = value()?;
+                trace_filter.range = match range.split_once('-') {
+                    Some((start, end)) => match (parse_addr(start), parse_addr(end)) {
+                        (Some(start), Some(end)) if start <= end => Some((start, end)),
+                        _ => None,
+                    },
+                    None => None,
+                };
+                if trace_filter.range.is_none() {
+                    return Err(format!("invalid address range {} for --trace-range", range));
+                }
+            },
+            "--trace-class" => {
+                trace_filter.classes = value()?.split(',')
+                    .map(|class| class.trim().parse())
+                    .collect::<Result<_, _>>()?;
+            },
+            "--trace-limit" => {
+                trace_limit_mb = parse(&opt, &value()?)?;
+                if trace_limit_mb == 0 {
+                    return Err("--trace-limit must be at least 1".to_string());
+                }
+            },
+            "--profile" => profile = Some(value()?),
+            "--profile-json" => profile_json = Some(value()?),
+            "--coverage" => coverage = Some(value()?),
+            "--sanitize" => sanitize = true,
+            _ if opt.starts_with('-') && opt.len() > 1 =>
+                return Err(format!("unknown option {}", opt)),
+            _ => {
+                if chip8_file.is_some() {
+                    return Err(format!("unexpected argument {}, only one ROM can be run", opt));
+                }
+                chip8_file = Some(opt);
+            },
+        }
+        args.no_value()?;
+    }
+
+    let chip8_filepath = match chip8_file {
+        Some(path) => path,
+        None => return Err("no ROM given".to_string()),
+    };
+
+    Ok(Command::Run(Box::new(Config {
+        chip8_filepath,
+        quirks,
+        instructions_per_frame,
+        seed,
+        load_addr,
+        rewind_seconds,
+        audio,
+        scale,
+        fg,
+        bg,
+        fullscreen,
+        headless,
+        frames,
+        rom_db,
+        debug,
+        gdb,
+        trace,
+        trace_format,
+        trace_filter,
+        trace_limit_mb,
+        profile,
+        profile_json,
+        coverage,
+        sanitize,
+    })))
+}
+
+fn parse<T: FromStr>(opt: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value {} for {}", value, opt))
+}
+
+// "ffffff", "#ffffff" or "0xffffff"
+fn parse_colour(opt: &str, value: &str) -> Result<(u8, u8, u8), String> {
+    let digits = value.trim_start_matches('#').trim_start_matches("0x");
+    match u32::from_str_radix(digits, 16) {
+        Ok(rgb) if digits.len() == 6 =>
+            Ok(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        _ => Err(format!("invalid colour {} for {}, expected RRGGBB", value, opt)),
+    }
+}
+
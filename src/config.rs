@@ -0,0 +1,98 @@
+/*
+ * Settings read from TOML files. ~/.config/chip_8/config.toml (or
+ * $XDG_CONFIG_HOME/chip_8/config.toml) applies to every ROM, <rom>.toml
+ * next to the ROM overrides it for that ROM only:
+ *
+ * [keys]
+ * # linear, vip or numpad
+ * preset = "vip"
+ * # host keys for a CHIP-8 key, by SDL scancode name; replaces what the
+ * # preset binds to that key
+ * bind = { A = ["Z", "Keypad 0"], 0 = ["X"] }
+ *
+ * [gamepad]
+ * # how far a stick has to move before it presses a key, 0-1
+ * deadzone = 0.3
+ * # controller buttons and stick directions for a CHIP-8 key, by SDL
+ * # name; a player table replaces the whole default layout of that player
+ * player1 = { 1 = ["dpup", "lefty-"], 4 = ["dpdown", "lefty+"] }
+ * player2 = { C = ["dpup", "lefty-"], D = ["dpdown", "lefty+"] }
+ */
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub keys: KeysConfig,
+    pub gamepad: GamepadConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    pub preset: Option<String>,
+    pub bind: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadConfig {
+    pub deadzone: Option<f32>,
+    pub player1: Option<BTreeMap<String, Vec<String>>>,
+    pub player2: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl FileConfig {
+    // global config merged with the one of `rom_path`, missing files are
+    // the same as empty ones
+    pub fn load(rom_path: &str) -> Result<FileConfig, String> {
+        let mut cfg = FileConfig::default();
+        if let Some(path) = global_path() {
+            cfg.merge(read(&path)?);
+        }
+        cfg.merge(read(Path::new(&format!("{}.toml", rom_path)))?);
+        Ok(cfg)
+    }
+
+    fn merge(&mut self, other: FileConfig) {
+        if other.keys.preset.is_some() {
+            self.keys.preset = other.keys.preset;
+        }
+        self.keys.bind.extend(other.keys.bind);
+
+        let pad = other.gamepad;
+        if pad.deadzone.is_some() {
+            self.gamepad.deadzone = pad.deadzone;
+        }
+        if pad.player1.is_some() {
+            self.gamepad.player1 = pad.player1;
+        }
+        if pad.player2.is_some() {
+            self.gamepad.player2 = pad.player2;
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<FileConfig, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(FileConfig::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+fn global_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("chip_8").join("config.toml"))
+}
@@ -1,14 +1,34 @@
 pub const REGISTER_COUNT:     usize = 16;
 pub const STACK_SIZE:         usize = 16;
-pub const RAM_SIZE:           usize = 4096;
+// XO-CHIP address space, other platforms only get the first 4 KiB of it
+pub const RAM_SIZE:           usize = 0x10000;
+// CHIP-8 and SUPER-CHIP memory
+pub const CHIP8_RAM_SIZE:     usize = 0x1000;
 pub const START_ADDR:         u16   = 0x200;
 
 pub const SCR_WIDTH:          usize = 64;
 pub const SCR_HEIGHT:         usize = 32;
+// SUPER-CHIP high resolution mode
+pub const HIRES_SCR_WIDTH:    usize = 128;
+pub const HIRES_SCR_HEIGHT:   usize = 64;
+
+pub const FONT_ADDR:          u16   = 0x0;
+pub const BIG_FONT_ADDR:      u16   = 0x50;
+pub const RPL_FLAGS_COUNT:    usize = 16;
+
+// XO-CHIP
+pub const PLANE_COUNT:        usize = 2;
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+pub const DEFAULT_PITCH:      u8    = 64;
 
 //pub const CLOCK_RATE_MS:      u32   = ((1.0/60.0)*1000.0+0.5) as u32;
+pub const FRAME_RATE:         u32   = 60;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 12;
+// frames the scheduler is allowed to fall behind before it gives up catching up
+pub const MAX_FRAME_LAG:      u32   = 5;
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+pub const MAX_REWIND_SECONDS: u32 = 600;
 
-#[allow(dead_code)]
 pub const FONTS: [[u8; 5]; 16] = [[0xf0, 0x90, 0x90, 0x90, 0xf0],
                               [0x20, 0x60, 0x20, 0x20, 0x70],
                               [0xf0, 0x10, 0xf0, 0x80, 0xf0],
@@ -25,3 +45,22 @@ pub const FONTS: [[u8; 5]; 16] = [[0xf0, 0x90, 0x90, 0x90, 0xf0],
                               [0xe0, 0x90, 0x90, 0x90, 0xe0],
                               [0xf0, 0x80, 0xf0, 0x80, 0xf0],
                               [0xf0, 0x80, 0xf0, 0x80, 0x80]];
+
+// SUPER-CHIP 8x10 font, A-F as extended by XO-CHIP
+pub const BIG_FONTS: [[u8; 10]; 16] = [
+    [0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff],
+    [0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xff, 0xff],
+    [0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff],
+    [0xff, 0xff, 0x03, 0x03, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff],
+    [0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0x03, 0x03],
+    [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff],
+    [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff],
+    [0xff, 0xff, 0x03, 0x03, 0x06, 0x0c, 0x18, 0x18, 0x18, 0x18],
+    [0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff],
+    [0xff, 0xff, 0xc3, 0xc3, 0xff, 0xff, 0x03, 0x03, 0xff, 0xff],
+    [0x7e, 0xff, 0xc3, 0xc3, 0xc3, 0xff, 0xff, 0xc3, 0xc3, 0xc3],
+    [0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc, 0xc3, 0xc3, 0xfc, 0xfc],
+    [0x3c, 0xff, 0xc3, 0xc0, 0xc0, 0xc0, 0xc0, 0xc3, 0xff, 0x3c],
+    [0xfc, 0xfe, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xfe, 0xfc],
+    [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff],
+    [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0]];
@@ -0,0 +1,286 @@
+/*
+ * Code coverage: one bit per address for every instruction that ran, and
+ * for every skip which of its two ways, skipping or going on with the
+ * next instruction, was taken.
+ *
+ * The report disassembles the ROM with every executed address as an
+ * extra entry point, so code only reached through BNNN and the like is
+ * listed as code too, and marks each line:
+ *
+ *     +      ran
+ *     #####  code that never ran
+ *     -      data
+ *
+ * The summary counts instructions (lcov's lines), subroutines (its
+ * functions) and the two ways of every skip (its branches) in the ROM.
+ */
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+use crate::cpu::{Cpu, InstructionOrd};
+use crate::debug::ExecHook;
+use crate::disasm::{self, Disassembly, Item, Syntax};
+use crate::instruction::Instruction;
+
+// column the branch comments start at
+const BRANCH_COLUMN: usize = 48;
+
+// one bit per address of memory
+pub struct Bitmap(Vec<u8>);
+
+impl Bitmap {
+    // for `size` bytes of memory
+    pub fn new(size: usize) -> Self {
+        Bitmap(vec![0; size.div_ceil(8)])
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.len() * 8
+    }
+
+    // sets addr..addr+len, whatever lies past the end of the bitmap is ignored
+    pub fn set(&mut self, addr: usize, len: usize) {
+        for addr in addr..(addr + len).min(self.size()) {
+            self.0[addr / 8] |= 1 << (addr % 8);
+        }
+    }
+
+    pub fn get(&self, addr: usize) -> bool {
+        addr < self.size() && self.0[addr / 8] & (1 << (addr % 8)) != 0
+    }
+
+    // first address in addr..addr+len that is set
+    pub fn first(&self, addr: usize, len: usize) -> Option<usize> {
+        (addr..addr + len).find(|&addr| self.get(addr))
+    }
+
+    pub fn addrs(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.size()).filter(|&addr| self.get(addr)).map(|addr| addr as u16)
+    }
+}
+
+// Memory the tools keep track of: `memory_size` bytes as in
+// Cpu::memory_size, or up to the end of `rom` when it doesn't fit in there.
+pub fn tracked_size(memory_size: usize, rom: &[u8], load_addr: u16) -> usize {
+    memory_size.max(load_addr as usize + rom.len())
+}
+
+// (hit, found) pairs like in lcov's summary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub lines: (usize, usize),
+    pub functions: (usize, usize),
+    pub branches: (usize, usize),
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rate = |(hit, found): (usize, usize), name: &str| {
+            if found == 0 {
+                format!("no data found ({})", name)
+            } else {
+                format!("{:.1}% ({} of {} {})", 100.0 * hit as f64 / found as f64, hit, found, name)
+            }
+        };
+        writeln!(f, "Summary coverage rate:")?;
+        writeln!(f, "  lines......: {}", rate(self.lines, "lines"))?;
+        writeln!(f, "  functions..: {}", rate(self.functions, "functions"))?;
+        write!(f, "  branches...: {}", rate(self.branches, "branches"))
+    }
+}
+
+pub struct Coverage {
+    rom: Vec<u8>,
+    load_addr: u16,
+    executed: Bitmap,
+    // skips that skipped and skips that didn't
+    skipped: Bitmap,
+    not_skipped: Bitmap,
+    pc: u16,
+}
+
+impl Coverage {
+    // `rom` as it was loaded at `load_addr`, before the program could
+    // change it, into `memory_size` bytes of memory
+    pub fn new(rom: &[u8], load_addr: u16, memory_size: usize) -> Self {
+        let size = tracked_size(memory_size, rom, load_addr);
+        Self {
+            rom: rom.to_vec(),
+            load_addr,
+            executed: Bitmap::new(size),
+            skipped: Bitmap::new(size),
+            not_skipped: Bitmap::new(size),
+            pc: load_addr,
+        }
+    }
+
+    fn in_rom(&self, addr: u16) -> bool {
+        addr >= self.load_addr && (addr as usize) < self.load_addr as usize + self.rom.len()
+    }
+
+    fn disassembly(&self) -> Disassembly {
+        let entries: Vec<u16> = self.executed.addrs().filter(|&addr| self.in_rom(addr)).collect();
+        disasm::disassemble_from(&self.rom, self.load_addr, &entries)
+    }
+
+    pub fn summary(&self) -> Summary {
+        self.count(&self.disassembly())
+    }
+
+    fn count(&self, disassembly: &Disassembly) -> Summary {
+        let mut summary = Summary::default();
+        let mut code = BTreeSet::new();
+        let mut subs = BTreeSet::from([self.load_addr]);
+        for line in disassembly.lines.iter() {
+            if let Item::Code(instruction) = line.item {
+                code.insert(line.addr);
+                summary.lines.0 += self.executed.get(line.addr as usize) as usize;
+                summary.lines.1 += 1;
+                if let Instruction::Call(nnn) = instruction {
+                    subs.insert(nnn);
+                }
+                if instruction.is_skip() {
+                    summary.branches.0 += self.skipped.get(line.addr as usize) as usize
+                        + self.not_skipped.get(line.addr as usize) as usize;
+                    summary.branches.1 += 2;
+                }
+            }
+        }
+        for addr in subs.into_iter().filter(|addr| code.contains(addr)) {
+            summary.functions.0 += self.executed.get(addr as usize) as usize;
+            summary.functions.1 += 1;
+        }
+        summary
+    }
+
+    // the annotated disassembly with the summary at the top
+    pub fn report(&self) -> String {
+        let disassembly = self.disassembly();
+        let mut out = String::new();
+        for line in self.count(&disassembly).to_string().lines() {
+            let _ = writeln!(out, "; {}", line);
+        }
+        let outside = self.executed.addrs().filter(|&addr| !self.in_rom(addr)).count();
+        if outside > 0 {
+            let _ = writeln!(out, "; {} executed addresses outside the ROM", outside);
+        }
+        let _ = writeln!(out);
+
+        for line in disassembly.lines.iter() {
+            if let Some(label) = disassembly.labels.get(&line.addr) {
+                let _ = writeln!(out, "{}:", label);
+            }
+            let (mark, text) = match line.item {
+                Item::Code(instruction) => (
+                    if self.executed.get(line.addr as usize) { "+" } else { "#####" },
+                    disasm::format_instruction(&instruction, Syntax::Cowgod, &disassembly.labels),
+                ),
+                Item::Data | Item::Sprite => ("-", disasm::format_data(&line.bytes, Syntax::Cowgod)),
+            };
+            let mut text = format!("{:>5}  0x{:03x}  {}", mark, line.addr, text);
+            if matches!(line.item, Item::Code(instruction) if instruction.is_skip()) {
+                let taken = |hit: bool| if hit { "taken" } else { "never" };
+                let _ = write!(text, "{:pad$}; skip {}, next {}", "",
+                    taken(self.skipped.get(line.addr as usize)), taken(self.not_skipped.get(line.addr as usize)),
+                    pad = BRANCH_COLUMN.saturating_sub(text.len()).max(1));
+            }
+            let _ = writeln!(out, "{}", text);
+        }
+        out
+    }
+}
+
+impl ExecHook for Coverage {
+    fn before(&mut self, cpu: &Cpu, _instruction: &Instruction) -> ControlFlow<()> {
+        self.pc = cpu.pc();
+        ControlFlow::Continue(())
+    }
+
+    fn after(&mut self, _cpu: &Cpu, instruction: &Instruction, ord: InstructionOrd) {
+        self.executed.set(self.pc as usize, 1);
+        if instruction.is_skip() {
+            match ord {
+                InstructionOrd::Skip => self.skipped.set(self.pc as usize, 1),
+                _ => self.not_skipped.set(self.pc as usize, 1),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_asm;
+    use crate::consts::{CHIP8_RAM_SIZE, RAM_SIZE};
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn bitmap_covers_the_platform_memory() {
+        let rom = [0; 0x100];
+        assert_eq!(Bitmap::new(tracked_size(CHIP8_RAM_SIZE, &rom, 0x200)).size(), CHIP8_RAM_SIZE);
+        assert_eq!(Bitmap::new(tracked_size(RAM_SIZE, &rom, 0x200)).size(), RAM_SIZE);
+        // a ROM too big for the platform is still covered
+        let big = vec![0; 0x1000];
+        assert_eq!(tracked_size(CHIP8_RAM_SIZE, &big, 0x200), 0x1200);
+
+        let mut bitmap = Bitmap::new(16);
+        bitmap.set(14, 4);
+        assert_eq!(bitmap.addrs().collect::<Vec<_>>(), [14, 15]);
+        assert!(!bitmap.get(16));
+        assert_eq!(bitmap.first(0, 16), Some(14));
+    }
+
+    #[test]
+    fn counts_lines_functions_and_branches() {
+        let rom = chip8_asm![
+            "main:",
+            "    CALL sub",
+            "    SE V0, 1",
+            "    JP main",
+            "done:",
+            "    JP done",
+            "sub:",
+            "    ADD V0, 1",
+            "    RET",
+            "unused:",
+            "    RET",
+        ];
+        let mut cpu = Cpu::new(rom.clone(), Quirks::MODERN).unwrap();
+        let mut coverage = Coverage::new(&rom, cpu.pc(), cpu.memory_size());
+        assert!(cpu.run_frame_with(Keypad::new(), 10, &mut coverage).unwrap().is_continue());
+        let summary = coverage.summary();
+        // nothing jumps to the last RET, so it is data
+        assert_eq!(summary.lines, (5, 6));
+        assert_eq!(summary.functions, (2, 2));
+        assert_eq!(summary.branches, (1, 2));
+
+        let report = coverage.report();
+        assert!(report.contains("skip taken, next never"), "{}", report);
+        assert!(report.contains("#####  0x204  JP main"), "{}", report);
+        assert!(report.contains("    -  0x20c"), "{}", report);
+    }
+
+    #[test]
+    fn skips_over_long_instructions() {
+        let rom = chip8_asm![
+            "    SE V0, 0",
+            "    LD I, LONG 0x1234",
+            "    SNE V0, 0",
+            "    CLS",
+            "done:",
+            "    JP done",
+        ];
+        let mut cpu = Cpu::new(rom.clone(), Quirks::XO_CHIP).unwrap();
+        let mut coverage = Coverage::new(&rom, cpu.pc(), cpu.memory_size());
+        assert!(cpu.run_frame_with(Keypad::new(), 4, &mut coverage).unwrap().is_continue());
+        assert_eq!(cpu.pc(), 0x20a);
+
+        assert!(coverage.skipped.get(0x200) && !coverage.not_skipped.get(0x200));
+        assert!(!coverage.skipped.get(0x206) && coverage.not_skipped.get(0x206));
+        assert!(!coverage.executed.get(0x202));
+    }
+}
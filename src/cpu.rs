@@ -1,11 +1,22 @@
-use std::process;
-use rand::{thread_rng, Rng};
+use std::ops::ControlFlow;
 
 use crate::consts::*;
-
-enum InstructionOrd {
+use crate::debug::{ExecHook, MemAccess};
+use crate::error::{Chip8Error, Fault, LoadError};
+use crate::quirks::{Platform, Quirks};
+use crate::instruction::{DecodeError, Instruction};
+use crate::keypad::Keypad;
+use crate::rng::{self, RandomSource};
+use crate::savestate::Snapshot;
+
+// where the program goes on after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionOrd {
+    // the instruction after it
     Next,
+    // over the instruction after it
     Skip,
+    // this address
     Jump(u16),
 }
 
@@ -20,14 +31,31 @@ pub struct Cpu {
     dt: u8, // delay timer
     st: u8, // sound timer
 
-    keys: [bool; 16],
+    keys: Keypad,
     key_waiting: bool, 
     key_to_store: Option<usize>,
+    // key FX0A saw go down and now waits to be released
+    key_held: Option<u8>,
 
-    pub vmem: [[u8; SCR_HEIGHT]; SCR_WIDTH],
+    // sized for hi-res, lo-res mode only uses the top left 64x32 corner.
+    // Every pixel is a bitmask of the XO-CHIP planes it is lit on.
+    pub vmem: [[u8; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH],
     pub vmem_changed: bool,
+    pub hires: bool,
+
+    rpl: [u8; RPL_FLAGS_COUNT],
+    halted: bool,
+
+    // XO-CHIP bitplanes selected for drawing, bit 0 is plane 1
+    planes: u8,
+    // XO-CHIP audio pattern buffer and playback pitch
+    pattern: [u8; AUDIO_PATTERN_SIZE],
+    pitch: u8,
     
     cycle: usize,
+
+    quirks: Quirks,
+    rng: Box<dyn RandomSource>,
 }
 
 impl std::fmt::Display for Cpu {
@@ -43,22 +71,6 @@ impl std::fmt::Display for Cpu {
 
 impl Cpu {
 
-    fn trace_instructions(&self) {
-        //println!("{:?}", self.mem[(self.pc-10) as usize..(self.pc+10) as usize]);
-        let mut row = 0;
-        for i in 
-            self.mem[(self.pc-10) as usize..(self.pc+10) as usize].iter()
-        {
-            print!("0x{:x} ", *i);
-            row += 1;
-            if row == 6 {
-                println!("");
-                row = 0;
-            }
-        }
-    }
-
-
     fn format_registers(&self) -> String {
         format!("[V[0x0]:({}), \
                  V[0x1]:({}), \
@@ -82,35 +94,50 @@ impl Cpu {
                  self.v[0xC], self.v[0xD], self.v[0xE], self.v[0xF])
     }
 
-    // loading fonts in first 80 bytes of memory
-    fn load_fonts(mem: &mut [u8]) { 
+    // loading fonts in first 80 bytes of memory, big fonts right after them
+    // on the platforms that have them
+    fn load_fonts(mem: &mut [u8], platform: Platform) { 
         for i in 0..16 {
             for j in 0..5 {
-                mem[i*5+j] = FONTS[i][j];
+                mem[FONT_ADDR as usize+i*5+j] = FONTS[i][j];
+            }
+            if !platform.big_font() {
+                continue;
+            }
+            for j in 0..10 {
+                mem[BIG_FONT_ADDR as usize+i*10+j] = BIG_FONTS[i][j];
             }
         }
     }
 
-    pub fn new(file: Vec<u8>) -> Self {
-        if file.len() > RAM_SIZE && file.len() == 0 {
-            eprintln!("Can't read chip-8 file because its too large\
-                either its 0 size");
-            process::exit(1);
-        }
+    pub fn new(file: Vec<u8>, quirks: Quirks) -> Result<Self, LoadError> {
         // Most Chip-8 programs start at location 0x200 
+        Cpu::with_load_addr(file, quirks, START_ADDR)
+    }
+
+    // for programs that start somewhere else, like the ETI 660 ones at 0x600
+    pub fn with_load_addr(file: Vec<u8>, quirks: Quirks, load_addr: u16)
+        -> Result<Self, LoadError>
+    {
+        if file.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if !Cpu::fits(&file, load_addr, quirks.platform) {
+            return Err(LoadError::TooLarge { len: file.len(), load_addr });
+        }
         let mut memory: [u8; RAM_SIZE] = [0; RAM_SIZE];
 
         for (i, val) in file.iter().enumerate() {
-            memory[0x200+i] = *val;
+            memory[load_addr as usize + i] = *val;
         }
 
-        Cpu::load_fonts(&mut memory);
+        Cpu::load_fonts(&mut memory, quirks.platform);
 
-        Self {
+        Ok(Self {
             // cpu and mem
             v: [0; REGISTER_COUNT],
             i: 0,
-            pc: START_ADDR,
+            pc: load_addr,
             sp: 0,
             stack: [0; STACK_SIZE],
             mem: memory,
@@ -120,234 +147,645 @@ impl Cpu {
             st: 0,
 
             // keyboard
-            keys: [false; 16],
+            keys: Keypad::new(),
             key_waiting: false,
             key_to_store: None,
+            key_held: None,
 
             //video,
-            vmem: [[0x0; SCR_HEIGHT]; SCR_WIDTH],
+            vmem: [[0x0; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH],
             vmem_changed: false,
+            hires: false,
+
+            rpl: [0; RPL_FLAGS_COUNT],
+            halted: false,
+
+            planes: 0x1,
+            pattern: [0; AUDIO_PATTERN_SIZE],
+            pitch: DEFAULT_PITCH,
 
             cycle: 0,
-        }
+
+            quirks,
+            rng: rng::default_rng(None),
+        })
     }
     
+    // whether `file` can be loaded at `load_addr` on `platform`
+    pub fn fits(file: &[u8], load_addr: u16, platform: Platform) -> bool {
+        !file.is_empty() && file.len() <= platform.memory_size().saturating_sub(load_addr as usize)
+    }
+
     // stack is used for stack frames
-    fn push(&mut self) {
+    fn push(&mut self) -> Result<(), Fault> {
+        if self.sp as usize + 1 >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
         self.sp += 1;
-        self.stack[self.sp as usize] = self.pc + 2;
+        self.stack[self.sp as usize] = self.pc.wrapping_add(2);
         //println!("push sp: {}", self.sp);
+        Ok(())
     }
 
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, Fault> {
+        if self.sp == 0 {
+            return Err(Fault::StackUnderflow);
+        }
         let n = self.stack[self.sp as usize];
         self.sp -= 1;
         //println!("pop sp: {}", self.sp);
-        n
+        Ok(n)
     }
 
-    pub fn tick(&mut self, keys: [bool; 16]) {
-        self.cycle += 1;
-        self.keys = keys;
-        self.vmem_changed = false;
+    fn read_mem(&self, addr: usize) -> Result<u8, Fault> {
+        match self.mem[..self.memory_size()].get(addr) {
+            Some(val) => Ok(*val),
+            None => Err(Fault::MemoryOutOfBounds(addr)),
+        }
+    }
 
-        if self.key_waiting {
-            for (i, key) in self.keys.iter().enumerate() {
-                if *key {
-                    match self.key_to_store {
-                        Some(t) => self.v[t] = i as u8,
-                        None => panic!("Uncreachable"),
-                    }
-                    self.key_waiting = false;
-                }
+    fn write_mem(&mut self, addr: usize, val: u8) -> Result<(), Fault> {
+        let size = self.memory_size();
+        match self.mem[..size].get_mut(addr) {
+            Some(cell) => {
+                *cell = val;
+                Ok(())
+            },
+            None => Err(Fault::MemoryOutOfBounds(addr)),
+        }
+    }
+
+    // copy of the complete machine state
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack,
+            mem: Box::new(self.mem),
+            dt: self.dt,
+            st: self.st,
+            key_waiting: self.key_waiting,
+            key_to_store: self.key_to_store,
+            key_held: self.key_held,
+            vmem: Box::new(self.vmem),
+            hires: self.hires,
+            rpl: self.rpl,
+            halted: self.halted,
+            planes: self.planes,
+            pattern: self.pattern,
+            pitch: self.pitch,
+            cycle: self.cycle as u64,
+        }
+    }
+
+    // puts the machine back into the state of `snapshot`, quirks and the
+    // random number generator are left alone
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.v = snapshot.v;
+        self.i = snapshot.i;
+        self.pc = snapshot.pc;
+        self.sp = snapshot.sp;
+        self.stack = snapshot.stack;
+        self.mem = *snapshot.mem;
+        self.dt = snapshot.dt;
+        self.st = snapshot.st;
+        self.key_waiting = snapshot.key_waiting;
+        self.key_to_store = snapshot.key_to_store;
+        self.key_held = snapshot.key_held;
+        self.vmem = *snapshot.vmem;
+        self.hires = snapshot.hires;
+        self.rpl = snapshot.rpl;
+        self.halted = snapshot.halted;
+        self.planes = snapshot.planes;
+        self.pattern = snapshot.pattern;
+        self.pitch = snapshot.pitch;
+        self.cycle = snapshot.cycle as usize;
+        self.vmem_changed = true;
+    }
+
+    // replaces the generator used by CXKK
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    // width and height of the display in the current resolution mode
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_SCR_WIDTH, HIRES_SCR_HEIGHT)
+        } else {
+            (SCR_WIDTH, SCR_HEIGHT)
+        }
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    // the buzzer sounds for as long as the sound timer is non-zero
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
+    }
+
+    // true once the program executed 00FD
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    // 128 one-bit samples loaded by F002
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.pattern
+    }
+
+    // playback rate of the audio pattern in Hz as set by FX3A
+    pub fn audio_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // what the buzzer plays on XO-CHIP, None for the plain tone: on the
+    // other platforms and until F002 loads a pattern that isn't silent
+    pub fn sound_pattern(&self) -> Option<([u8; AUDIO_PATTERN_SIZE], f32)> {
+        if self.quirks.platform != Platform::XoChip || self.pattern.iter().all(|&b| b == 0) {
+            return None;
+        }
+        Some((self.pattern, self.audio_rate()))
+    }
+
+    // registers, stack and memory, for debuggers
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn v(&self) -> &[u8; REGISTER_COUNT] {
+        &self.v
+    }
+
+    pub fn set_v(&mut self, x: usize, val: u8) {
+        self.v[x] = val;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    // slot 0 is never used, so at most STACK_SIZE - 1 calls
+    pub fn set_sp(&mut self, sp: u8) -> Result<(), Fault> {
+        if sp as usize >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+        self.sp = sp;
+        Ok(())
+    }
+
+    // return addresses of the active subroutine calls, innermost last
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[1..=self.sp as usize]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn set_delay_timer(&mut self, dt: u8) {
+        self.dt = dt;
+    }
+
+    pub fn set_sound_timer(&mut self, st: u8) {
+        self.st = st;
+    }
+
+    pub fn memory(&self) -> &[u8; RAM_SIZE] {
+        &self.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; RAM_SIZE] {
+        &mut self.mem
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.quirks.platform
+    }
+
+    // how much of `memory()` the platform has, 4 KiB unless XO-CHIP
+    pub fn memory_size(&self) -> usize {
+        self.quirks.platform.memory_size()
+    }
+
+    // instructions executed so far, ticks spent waiting in FX0A included
+    pub fn cycle(&self) -> usize {
+        self.cycle
+    }
+
+    // true while FX0A waits for a key
+    pub fn waiting_for_key(&self) -> bool {
+        self.key_waiting
+    }
+
+    // the instruction at pc
+    pub fn current_instruction(&self) -> Result<Instruction, Chip8Error> {
+        self.fetch().map(|(_, instruction)| instruction)
+    }
+
+    // memory `instruction` reads or writes when run in the current state,
+    // instruction fetches don't count
+    pub fn memory_access(&self, instruction: &Instruction) -> Option<MemAccess> {
+        let i = self.i as usize;
+        let access = |len: usize, write: bool| Some(MemAccess { addr: i, len, write });
+        let planes = (self.planes as u32 & ((1 << PLANE_COUNT) - 1)).count_ones() as usize;
+        match *instruction {
+            Instruction::SaveRange(x, y) => access(x.abs_diff(y) + 1, true),
+            Instruction::LoadRange(x, y) => access(x.abs_diff(y) + 1, false),
+            Instruction::Draw(_, _, 0) if planes > 0 => access(32 * planes, false),
+            Instruction::Draw(_, _, n) if planes > 0 => access(n as usize * planes, false),
+            Instruction::Audio => access(AUDIO_PATTERN_SIZE, false),
+            Instruction::Bcd(_) => access(3, true),
+            Instruction::Store(x) => access(x + 1, true),
+            Instruction::Load(x) => access(x + 1, false),
+            _ => None,
+        }
+    }
+
+    // Emulates one 60 Hz frame: `instructions` ticks followed by one timer
+    // update. vmem_changed reports whether anything was drawn in the frame.
+    pub fn run_frame(&mut self, keys: Keypad, instructions: u32)
+        -> Result<(), Chip8Error>
+    {
+        self.run_frame_with(keys, instructions, &mut ()).map(|_| ())
+    }
+
+    // Like `run_frame`, with `hook` seeing every instruction. When the
+    // hook breaks the rest of the frame and the timer update are skipped.
+    pub fn run_frame_with(&mut self, keys: Keypad, instructions: u32, hook: &mut dyn ExecHook)
+        -> Result<ControlFlow<()>, Chip8Error>
+    {
+        self.vmem_changed = false;
+        for _ in 0..instructions {
+            if self.halted {
+                break;
+            }
+            if self.tick_with(keys, hook)?.is_break() {
+                return Ok(ControlFlow::Break(()));
             }
+        }
+        self.tick_timers();
+        Ok(ControlFlow::Continue(()))
+    }
+
+    // decrements the delay and sound timers, call it at 60 Hz
+    pub fn tick_timers(&mut self) {
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+        if self.st > 0 {
+            self.st -= 1;
+        }
+    }
+
+    // Executes a single instruction. Timers are not touched here, and
+    // vmem_changed is only ever set, see `run_frame` for both.
+    pub fn tick(&mut self, keys: Keypad) -> Result<(), Chip8Error> {
+        self.tick_with(keys, &mut ()).map(|_| ())
+    }
+
+    // Like `tick`, with `hook` seeing the instruction before and after it
+    // runs. Ticks spent waiting in FX0A don't run an instruction and
+    // aren't shown to the hook.
+    pub fn tick_with(&mut self, keys: Keypad, hook: &mut dyn ExecHook)
+        -> Result<ControlFlow<()>, Chip8Error>
+    {
+        if self.halted {
+            return Ok(ControlFlow::Continue(()));
+        }
+        let next = if self.key_waiting {
+            None
         } else {
-            if self.cycle % 12 == 0 {
-                if self.dt > 0 {
-                    self.dt -= 1;
-                }
-                if self.st > 0 {
-                    self.st -= 1;
-                }
+            let (op, instruction) = self.fetch()?;
+            if hook.before(self, &instruction).is_break() {
+                return Ok(ControlFlow::Break(()));
             }
-            //self.trace_instructions();
-            let op = self.read_next_instruction();
-            self.instruction_parser(op);
+            Some((op, instruction))
+        };
+
+        self.cycle += 1;
+        // the same poll is handed to every tick of a frame, its press
+        // events only count the first time around
+        let fresh = keys.generation() != self.keys.generation();
+        self.keys = keys;
+
+        match next {
+            None => self.wait_for_key(fresh),
+            Some((op, instruction)) => {
+                let pc = self.pc;
+                let ord = self.instruction_parser(instruction).map_err(|fault| Chip8Error {
+                    pc,
+                    opcode: op,
+                    fault,
+                })?;
+                hook.after(self, &instruction, ord);
+            },
         }
+        Ok(ControlFlow::Continue(()))
     }
-    
-    fn read_next_instruction(&mut self) -> u16 {
-        u16::from_be_bytes(
-            [self.mem[self.pc as usize], self.mem[self.pc as usize+1]]
-        )
-    }
-
-    fn instruction_parser(&mut self, op: u16) {
-        let x = ((op << 4) >> 12) as usize;
-        let y = ((op << 8) >> 12) as usize;
-        let kk = ((op << 8) >> 8) as u8;
-        let n = ((op << 12) >> 12) as u8;
-        let nnn = ((op << 4) >> 4) as u16;
-
-        let programm_counter = match op >> 12 {
-            0x0  => {
-                if op == 0x00e0 { // CLR_SCR
-                    self.i_00e0()
-                } else if op == 0x00ee { // RET
-                    self.i_00ee()
+
+    // opcode at pc and the instruction it decodes to
+    fn fetch(&self) -> Result<(u16, Instruction), Chip8Error> {
+        let error = |opcode, fault| Chip8Error { pc: self.pc, opcode, fault };
+        let op = self.read_next_instruction().map_err(|fault| error(0, fault))?;
+        let instruction = match Instruction::decode(op) {
+            Ok(instruction) => instruction,
+            // F000 NNNN, the address is in the following word
+            Err(DecodeError::MissingOperand(_)) => {
+                let pc = self.pc as usize;
+                let next = self.read_mem(pc + 2)
+                    .and_then(|hi| Ok(u16::from_be_bytes([hi, self.read_mem(pc + 3)?])))
+                    .map_err(|fault| error(op, fault))?;
+                Instruction::decode_long(op, next)
+                    .map_err(|_| error(op, Fault::UnknownOpcode))?
+            },
+            Err(DecodeError::Unknown(_)) => return Err(error(op, Fault::UnknownOpcode)),
+        };
+        Ok((op, instruction))
+    }
+
+    fn read_next_instruction(&self) -> Result<u16, Fault> {
+        let pc = self.pc as usize;
+        if pc + 1 >= self.memory_size() {
+            return Err(Fault::PcOutOfBounds);
+        }
+        Ok(u16::from_be_bytes([self.mem[pc], self.mem[pc+1]]))
+    }
+
+    fn instruction_parser(&mut self, instruction: Instruction) -> Result<InstructionOrd, Fault> {
+        let programm_counter = self.execute(instruction)?;
+
+        match programm_counter {
+            InstructionOrd::Next => self.pc = self.pc.wrapping_add(2),
+            InstructionOrd::Skip => {
+                // F000 NNNN is twice as long, skip over the whole of it
+                let next = self.pc.wrapping_add(2) as usize;
+                if self.read_mem(next) == Ok(0xf0) && self.read_mem(next + 1) == Ok(0x00) {
+                    self.pc = self.pc.wrapping_add(6);
                 } else {
-                    // ignore this: This instruction is only used on the old computers on which
-                    // Chip-8 was originally implemented. It is ignored by modern interpreters. 
-                    self.i_0nnn()
+                    self.pc = self.pc.wrapping_add(4);
                 }
             },
+            InstructionOrd::Jump(nnn) => {
+                self.pc = nnn
+            },
+        }
+        Ok(programm_counter)
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<InstructionOrd, Fault> {
+        match instruction {
+            // clear the screen
+            Instruction::Cls => self.i_00e0(),
+            // return from a subroutine
+            Instruction::Ret => self.i_00ee(),
+            // scroll down/up n lines
+            Instruction::ScrollDown(n) => self.i_00cn(n),
+            Instruction::ScrollUp(n) => self.i_00dn(n),
+            // scroll right/left 4 pixels
+            Instruction::ScrollRight => self.i_00fb(),
+            Instruction::ScrollLeft => self.i_00fc(),
+            // exit the interpreter
+            Instruction::Exit => self.i_00fd(),
+            // switch between lo-res and hi-res
+            Instruction::LoRes => self.i_00fe(),
+            Instruction::HiRes => self.i_00ff(),
+            // ignore this: This instruction is only used on the old computers on which
+            // Chip-8 was originally implemented. It is ignored by modern interpreters. 
+            Instruction::Sys(_) => self.i_0nnn(),
             // jump to addr(nnn)
-            0x1 => self.i_1nnn(nnn),
+            Instruction::Jump(nnn) => self.i_1nnn(nnn),
             // call addr
-            0x2  => self.i_2nnn(nnn),
+            Instruction::Call(nnn) => self.i_2nnn(nnn),
             // Skip next instruction if Vx = kk.
-            0x3  => self.i_3xkk(x, kk),
+            Instruction::SkipEqByte(x, kk) => self.i_3xkk(x, kk),
             // Skip next instruction if Vx != kk.
-            0x4  => self.i_4xkk(x, kk),
+            Instruction::SkipNeByte(x, kk) => self.i_4xkk(x, kk),
             // Skip next instruction if Vx == Vy.
-            0x5  => self.i_5xy0(x, y),
+            Instruction::SkipEqReg(x, y) => self.i_5xy0(x, y),
+            // Save Vx..Vy to memory starting at I.
+            Instruction::SaveRange(x, y) => self.i_5xy2(x, y),
+            // Load Vx..Vy from memory starting at I.
+            Instruction::LoadRange(x, y) => self.i_5xy3(x, y),
             // put the value into register. Set Vx = kk.
-            0x6  => self.i_6xkk(x, kk),
+            Instruction::LoadByte(x, kk) => self.i_6xkk(x, kk),
             // ADD Vx, byte
-            0x7  => self.i_7xkk(x, kk),
-            // Operations with Vx and Vy registers
-            0x8  => {
-                // match least significant 4 bits
-                match n {
-                    // Stores the value of register Vy in register Vx.
-                    0x0 => self.i_8xy0(x, y),
-                    // OR V[x] with V[y]
-                    0x1 => self.i_8xy1(x, y),
-                    // AND V[x] with V[y]
-                    0x2 => self.i_8xy2(x, y),
-                    // XOR V[x] with V[y]
-                    0x3 => self.i_8xy3(x, y),
-                    // ADD Vx, Vy
-                    0x4 => self.i_8xy4(x, y),
-                    // SUB Vx, Vy
-                    0x5 => self.i_8xy5(x, y),
-                    // SHR Vx {, Vy}
-                    0x6 => self.i_8xy6(x, y),
-                    // SUBN Vx, Vy
-                    0x7 => self.i_8xy7(x, y),
-                    // SHL Vx {, Vy}
-                    0xE => self.i_8xye(x, y),
-                    e => panic!("undefined LS 4 bits: {} in 0x8", e),
-                }
-            },
+            Instruction::AddByte(x, kk) => self.i_7xkk(x, kk),
+            // Stores the value of register Vy in register Vx.
+            Instruction::Move(x, y) => self.i_8xy0(x, y),
+            // OR V[x] with V[y]
+            Instruction::Or(x, y) => self.i_8xy1(x, y),
+            // AND V[x] with V[y]
+            Instruction::And(x, y) => self.i_8xy2(x, y),
+            // XOR V[x] with V[y]
+            Instruction::Xor(x, y) => self.i_8xy3(x, y),
+            // ADD Vx, Vy
+            Instruction::Add(x, y) => self.i_8xy4(x, y),
+            // SUB Vx, Vy
+            Instruction::Sub(x, y) => self.i_8xy5(x, y),
+            // SHR Vx {, Vy}
+            Instruction::Shr(x, y) => self.i_8xy6(x, y),
+            // SUBN Vx, Vy
+            Instruction::SubN(x, y) => self.i_8xy7(x, y),
+            // SHL Vx {, Vy}
+            Instruction::Shl(x, y) => self.i_8xye(x, y),
             // Skip next instruction if Vx != Vy. 
-            0x9  => self.i_9xy0(x, y),
+            Instruction::SkipNeReg(x, y) => self.i_9xy0(x, y),
             // Set I = nnn.
-            0xA => self.i_annn(nnn),
+            Instruction::LoadI(nnn) => self.i_annn(nnn),
             // Jump to location nnn + V0.
-            0xB => self.i_bnnn(nnn),
+            Instruction::JumpOffset(x, nnn) => self.i_bnnn(x, nnn),
             // Set Vx = random byte AND kk.
-            0xC => self.i_cxkk(x, kk),
+            Instruction::Random(x, kk) => self.i_cxkk(x, kk),
             // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-            0xD => self.i_dxyn(x, y, n),
-            0xE => {
-                match kk {
-                    0x9E => self.i_ex9e(x),
-                    0xA1 => self.i_exa1(x),
-                    e    => panic!("Unknown LSB: {} in 0xE", e),
-                }
-            },
-            // watch the least siginficant byte, in this case kk and match it;
-            0xF => {
-                match kk {
-                    // Set Vx = delay timer value.
-                    0x07 => self.i_fx07(x),
-                    // Wait for a key press, store the value of the key in Vx.
-                    0x0A => self.i_fx0a(x),
-                    //  Set delay timer = Vx.
-                    0x15 => self.i_fx15(x),
-                    // Set sound timer = Vx.
-                    0x18 => self.i_fx18(x),
-                    // Set I = I + Vx.
-                    0x1E => self.i_fx1e(x),
-                    // Set I = location of sprite for digit Vx.
-                    0x29 => self.i_fx29(x),
-                    // Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                    0x33 => self.i_fx33(x),
-                    // Store registers V0 through Vx in memory starting at location I.
-                    0x55 => self.i_fx55(x),
-                    // Read registers V0 through Vx from memory starting at location I.
-                    0x65 => self.i_fx65(x),
-                    e => panic!("undefined LSB: 0x{:x} in 0xF pc: 0x{:x}", e, self.pc),
-                }
-            },
-            e  => panic!("Unknown instruction: {}", e),
-        };
-
-        match programm_counter {
-            InstructionOrd::Next => self.pc += 2,
-            InstructionOrd::Skip => self.pc += 4,
-            InstructionOrd::Jump(nnn) => {
-                self.pc = nnn
-            },
+            // With n == 0 draw a 16x16 sprite instead (SUPER-CHIP).
+            Instruction::Draw(x, y, 0) => self.i_dxy0(x, y),
+            Instruction::Draw(x, y, n) => self.i_dxyn(x, y, n),
+            // Skip next instruction if key Vx is (not) pressed.
+            Instruction::SkipKey(x) => self.i_ex9e(x),
+            Instruction::SkipNotKey(x) => self.i_exa1(x),
+            // Set I = the 16 bit word following this instruction.
+            Instruction::LoadILong(nnnn) => self.i_f000(nnnn),
+            // Select drawing planes.
+            Instruction::Plane(n) => self.i_fn01(n as usize),
+            // Load audio pattern from memory at I.
+            Instruction::Audio => self.i_f002(),
+            // Set Vx = delay timer value.
+            Instruction::LoadDelay(x) => self.i_fx07(x),
+            // Wait for a key press, store the value of the key in Vx.
+            Instruction::WaitKey(x) => self.i_fx0a(x),
+            //  Set delay timer = Vx.
+            Instruction::SetDelay(x) => self.i_fx15(x),
+            // Set sound timer = Vx.
+            Instruction::SetSound(x) => self.i_fx18(x),
+            // Set I = I + Vx.
+            Instruction::AddI(x) => self.i_fx1e(x),
+            // Set I = location of sprite for digit Vx.
+            Instruction::Font(x) => self.i_fx29(x),
+            // Set I = location of big sprite for digit Vx.
+            Instruction::BigFont(x) => self.i_fx30(x),
+            // Store BCD representation of Vx in memory locations I, I+1, and I+2.
+            Instruction::Bcd(x) => self.i_fx33(x),
+            // Set audio pitch = Vx.
+            Instruction::Pitch(x) => self.i_fx3a(x),
+            // Store registers V0 through Vx in memory starting at location I.
+            Instruction::Store(x) => self.i_fx55(x),
+            // Read registers V0 through Vx from memory starting at location I.
+            Instruction::Load(x) => self.i_fx65(x),
+            // Store V0 through Vx in RPL user flags.
+            Instruction::SaveFlags(x) => self.i_fx75(x),
+            // Read V0 through Vx from RPL user flags.
+            Instruction::LoadFlags(x) => self.i_fx85(x),
         }
     }
 
-    fn i_00e0(&mut self) -> InstructionOrd {
+    fn i_00e0(&mut self) -> Result<InstructionOrd, Fault> {
         //println!("00E0: Clear the display.");
 
-        self.vmem = [[0x0; SCR_HEIGHT]; SCR_WIDTH];
+        for column in self.vmem.iter_mut() {
+            for pixel in column.iter_mut() {
+                *pixel &= !self.planes;
+            }
+        }
         self.vmem_changed = true;
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     } 
 
-    fn i_00ee(&mut self) -> InstructionOrd {
-        let addr = self.pop();
+    fn i_00cn(&mut self, n: u8) -> Result<InstructionOrd, Fault> {
+        //println!("Scroll display {} lines down", n);
+
+        self.scroll(0, n as isize);
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_00dn(&mut self, n: u8) -> Result<InstructionOrd, Fault> {
+        //println!("Scroll display {} lines up", n);
+
+        self.scroll(0, -(n as isize));
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_00fb(&mut self) -> Result<InstructionOrd, Fault> {
+        //println!("Scroll display 4 pixels right");
+
+        self.scroll(4, 0);
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_00fc(&mut self) -> Result<InstructionOrd, Fault> {
+        //println!("Scroll display 4 pixels left");
+
+        self.scroll(-4, 0);
+        Ok(InstructionOrd::Next)
+    }
+
+    // move the selected planes by (dx, dy) pixels, shifting in blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (w, h) = self.screen_size();
+        let old = self.vmem;
+        for x in 0..w {
+            for y in 0..h {
+                let sx = x as isize - dx;
+                let sy = y as isize - dy;
+                let src = if sx >= 0 && sy >= 0 && (sx as usize) < w && (sy as usize) < h {
+                    old[sx as usize][sy as usize]
+                } else {
+                    0
+                };
+                self.vmem[x][y] = (old[x][y] & !self.planes) | (src & self.planes);
+            }
+        }
+        self.vmem_changed = true;
+    }
+
+    fn i_00fd(&mut self) -> Result<InstructionOrd, Fault> {
+        //println!("Exit interpreter");
+
+        self.halted = true;
+        Ok(InstructionOrd::Jump(self.pc))
+    }
+
+    fn i_00fe(&mut self) -> Result<InstructionOrd, Fault> {
+        //println!("Disable high resolution mode");
+
+        self.hires = false;
+        self.vmem = [[0x0; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH];
+        self.vmem_changed = true;
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_00ff(&mut self) -> Result<InstructionOrd, Fault> {
+        //println!("Enable high resolution mode");
+
+        self.hires = true;
+        self.vmem = [[0x0; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH];
+        self.vmem_changed = true;
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_00ee(&mut self) -> Result<InstructionOrd, Fault> {
+        let addr = self.pop()?;
 
         //println!("00EE: Return from subroutine to 0x{:x}", addr);
 
-        InstructionOrd::Jump(addr)
+        Ok(InstructionOrd::Jump(addr))
     }
 
-    fn i_0nnn(&mut self) -> InstructionOrd {
+    fn i_0nnn(&mut self) -> Result<InstructionOrd, Fault> {
         //println!("0nnn ignored");
 
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
 
-    fn i_1nnn(&mut self, nnn: u16) -> InstructionOrd {
+    fn i_1nnn(&mut self, nnn: u16) -> Result<InstructionOrd, Fault> {
         //println!("Jump to location (0x{:x})", nnn);
 
-        InstructionOrd::Jump(nnn)
+        Ok(InstructionOrd::Jump(nnn))
     }
 
-    fn i_2nnn(&mut self, nnn: u16) -> InstructionOrd {
+    fn i_2nnn(&mut self, nnn: u16) -> Result<InstructionOrd, Fault> {
         //println!("Call a subroutine at (0x{:x})", nnn);
 
-        self.push();
-        InstructionOrd::Jump(nnn)
+        self.push()?;
+        Ok(InstructionOrd::Jump(nnn))
     }
 
-    fn i_3xkk(&mut self, x: usize, kk: u8) -> InstructionOrd {
+    fn i_3xkk(&mut self, x: usize, kk: u8) -> Result<InstructionOrd, Fault> {
         //println!("Skip next instruction if V[0x{:x}]: ({}) == kk:({})", x, self.v[x as usize], kk);
 
         if self.v[x] == kk {
-            return InstructionOrd::Skip;
+            return Ok(InstructionOrd::Skip);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
     
-    fn i_4xkk(&mut self, x: usize, kk: u8) -> InstructionOrd {
+    fn i_4xkk(&mut self, x: usize, kk: u8) -> Result<InstructionOrd, Fault> {
         //println!("Skip next instruction if V[0x{:x}]: ({}) != kk:({})", x, self.v[x as usize], kk);
 
         if self.v[x] != kk {
-            return InstructionOrd::Skip;
+            return Ok(InstructionOrd::Skip);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_5xy0(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_5xy0(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Skip next instruction if V[0x{:x}]: ({}) != V[{}]: ({})",
         //    x,
         //    self.v[x as usize],
@@ -355,19 +793,19 @@ impl Cpu {
         //    self.v[y as usize]);
 
         if self.v[x] == self.v[y] {
-            return InstructionOrd::Skip;
+            return Ok(InstructionOrd::Skip);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_6xkk(&mut self, x: usize, kk: u8) -> InstructionOrd {
+    fn i_6xkk(&mut self, x: usize, kk: u8) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}] = kk:({})", x, kk);
 
         self.v[x] = kk;
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_7xkk(&mut self, x: usize, kk: u8) -> InstructionOrd {
+    fn i_7xkk(&mut self, x: usize, kk: u8) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}] = V[0x{:x}]: ({}) + kk:({})",
         //    x,
         //    x, 
@@ -378,20 +816,20 @@ impl Cpu {
         let val = kk as u16;
         let res: u16 = vx + val;
         self.v[x] = res as u8;
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy0(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy0(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}] = V[0x{:x}]: ({}).",
         //    x,
         //    y,
         //    self.v[y as usize]);
 
         self.v[x] = self.v[y]; 
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy1(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy1(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) OR V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],  
@@ -401,10 +839,13 @@ impl Cpu {
         //    self.v[y as usize]);
 
         self.v[x] |= self.v[y];
-        InstructionOrd::Next
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy2(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy2(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) AND V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],  
@@ -414,10 +855,13 @@ impl Cpu {
         //    self.v[y as usize]);
 
         self.v[x] &= self.v[y];
-        InstructionOrd::Next
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy3(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy3(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) XOR V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],  
@@ -427,10 +871,13 @@ impl Cpu {
         //    self.v[y as usize]);
 
         self.v[x] ^= self.v[y];
-        InstructionOrd::Next
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy4(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy4(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) + V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],
@@ -445,10 +892,10 @@ impl Cpu {
         self.v[x] = res as u8;
         
         if res > 255 { self.v[0xf] = 1 } else {  self.v[0xf] = 0 }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy5(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy5(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) - V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],
@@ -463,22 +910,23 @@ impl Cpu {
             self.v[0xf] = 0
         }
         self.v[x] = self.v[x].wrapping_sub(self.v[y]);
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy6(&mut self, x: usize, _: usize) -> InstructionOrd {
+    fn i_8xy6(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) SHR 1",
         //    x,
         //    self.v[x as usize],
         //    y,
         //    self.v[y as usize]);
 
-        self.v[0xf] = self.v[x] & 0x1;
-        self.v[x] >>= 1;
-        InstructionOrd::Next
+        let val = if self.quirks.shift_vx { self.v[x] } else { self.v[y] };
+        self.v[x] = val >> 1;
+        self.v[0xf] = val & 0x1;
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xy7(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_8xy7(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set Vx[0x{:x}]: ({}) = Vy[0x{:x}]: ({}) - Vx[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],
@@ -493,22 +941,41 @@ impl Cpu {
             self.v[0xf] = 0
         }
         self.v[x] = self.v[y].wrapping_sub(self.v[x]);
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_8xye(&mut self, x: usize, _: usize) -> InstructionOrd {
+    fn i_8xye(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}]: ({}) = V[0x{:x}]: ({}) SHL 1",
         //    x,
         //    self.v[x as usize],
         //    y,
         //    self.v[y as usize]);
 
-        self.v[0xf] = self.v[x] >> 7;
-        self.v[x] <<= 1;
-        InstructionOrd::Next
+        let val = if self.quirks.shift_vx { self.v[x] } else { self.v[y] };
+        self.v[x] = val << 1;
+        self.v[0xf] = val >> 7;
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_5xy2(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
+        //println!("Save V[0x{:x}]..V[0x{:x}] at I", x, y);
+
+        for (offset, reg) in register_range(x, y).enumerate() {
+            self.write_mem(self.i as usize + offset, self.v[reg])?;
+        }
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_5xy3(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
+        //println!("Load V[0x{:x}]..V[0x{:x}] from I", x, y);
+
+        for (offset, reg) in register_range(x, y).enumerate() {
+            self.v[reg] = self.read_mem(self.i as usize + offset)?;
+        }
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_9xy0(&mut self, x: usize, y: usize) -> InstructionOrd {
+    fn i_9xy0(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
         //println!("Skip next instruction if V[0x{:x}]: ({}) != V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize],
@@ -516,35 +983,35 @@ impl Cpu {
         //    self.v[y as usize]);
 
         if self.v[x] != self.v[y] {
-            return InstructionOrd::Skip;
+            return Ok(InstructionOrd::Skip);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_annn(&mut self, nnn: u16) -> InstructionOrd {
+    fn i_annn(&mut self, nnn: u16) -> Result<InstructionOrd, Fault> {
         //println!("Set I = 0x{:x}", nnn);
 
         self.i = nnn;
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_bnnn(&mut self, nnn: u16) -> InstructionOrd {
+    fn i_bnnn(&mut self, x: usize, nnn: u16) -> Result<InstructionOrd, Fault> {
         //println!("Jump to location (0x{:x}) + V[0x0]: ({})",
         //    nnn,
         //    self.v[0]);
 
-        InstructionOrd::Jump(nnn+self.v[0] as u16)
+        let offset = if self.quirks.jump_vx { self.v[x] } else { self.v[0] };
+        Ok(InstructionOrd::Jump(nnn+offset as u16))
     }
 
-    fn i_cxkk(&mut self, x: usize, kk: u8) -> InstructionOrd {
-        println!("Generating random number in range 0.255");
+    fn i_cxkk(&mut self, x: usize, kk: u8) -> Result<InstructionOrd, Fault> {
+        //println!("Generating random number in range 0.255");
 
-        let mut rng = thread_rng();
-        self.v[x] = rng.gen_range(0..255) & kk;
-        InstructionOrd::Next
+        self.v[x] = self.rng.next_byte() & kk;
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_dxyn(&mut self, x: usize, y: usize, n: u8) -> InstructionOrd {
+    fn i_dxyn(&mut self, x: usize, y: usize, n: u8) -> Result<InstructionOrd, Fault> {
         //println!("Drawing at ({}, {}) {} bytes",
         //    self.v[x],
         //    self.v[y],
@@ -576,113 +1043,433 @@ impl Cpu {
         }
         */
 
+        self.draw_sprite(x, y, n as usize, 1)?;
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_dxy0(&mut self, x: usize, y: usize) -> Result<InstructionOrd, Fault> {
+        //println!("Drawing 16x16 sprite at ({}, {})",
+        //    self.v[x],
+        //    self.v[y]);
+
+        self.draw_sprite(x, y, 16, 2)?;
+        Ok(InstructionOrd::Next)
+    }
+
+    // XOR `rows` rows of `row_bytes` bytes each from memory at I onto the
+    // screen at (Vx, Vy), VF is set on collision
+    fn draw_sprite(&mut self, x: usize, y: usize, rows: usize, row_bytes: usize)
+        -> Result<(), Fault>
+    {
+        let (w, h) = self.screen_size();
+        let width = row_bytes * 8;
+
+        // the starting position always wraps, the rest of the sprite
+        // either wraps too or is clipped depending on the quirk
+        let x0 = self.v[x] as usize % w;
+        let y0 = self.v[y] as usize % h;
+        let clip = self.quirks.clip_sprites;
+
+        // each selected plane takes its own copy of the sprite data,
+        // laid out one after another starting at I
+        let mut addr = self.i as usize;
+
         self.v[0x0f] = 0;
-        for byte in 0..n {
-            let y = (self.v[y] + byte) % SCR_HEIGHT as u8;
-            for bit in 0..8 {
-                let x = (self.v[x] + bit) % SCR_WIDTH as u8;
-                let color = (self.mem[(self.i + byte as u16) as usize] >> (7 - bit)) & 1;
-                self.v[0x0f] |= color & self.vmem[x as usize][y as usize];
-                self.vmem[x as usize][y as usize] ^= color;
+        for plane in 0..PLANE_COUNT {
+            let mask = 1 << plane;
+            if self.planes & mask == 0 {
+                continue;
+            }
+            for row in 0..rows {
+                let mut bits: u16 = 0;
+                for _ in 0..row_bytes {
+                    bits = (bits << 8) | self.read_mem(addr)? as u16;
+                    addr += 1;
+                }
+                if clip && y0 + row >= h {
+                    continue;
+                }
+                let y = (y0 + row) % h;
+                for bit in 0..width {
+                    if clip && x0 + bit >= w {
+                        break;
+                    }
+                    let x = (x0 + bit) % w;
+                    if (bits >> (width - 1 - bit)) & 1 == 1 {
+                        if self.vmem[x][y] & mask != 0 {
+                            self.v[0x0f] = 1;
+                        }
+                        self.vmem[x][y] ^= mask;
+                    }
+                }
             }
         }
 
         self.vmem_changed = true;
-        InstructionOrd::Next
+        Ok(())
+    }
+
+    // held, or tapped so quickly that it went up again within one poll
+    fn key_down(&self, key: u8) -> bool {
+        self.keys.is_pressed(key) || self.keys.just_pressed(key)
     }
 
-    fn i_ex9e(&mut self, x: usize) -> InstructionOrd {
+    // FX0A finishes once a key has gone down and come back up again,
+    // like on the COSMAC VIP
+    fn wait_for_key(&mut self, fresh: bool) {
+        let key = match self.key_held {
+            Some(key) => key,
+            None => {
+                let down = (0..16).find(|&k| self.keys.is_pressed(k)
+                    || (fresh && self.keys.just_pressed(k)));
+                match down {
+                    Some(key) => key,
+                    None => return,
+                }
+            },
+        };
+        if self.keys.is_pressed(key) {
+            self.key_held = Some(key);
+            return;
+        }
+        if let Some(t) = self.key_to_store {
+            self.v[t] = key;
+        }
+        self.key_waiting = false;
+        self.key_held = None;
+    }
+
+    fn i_ex9e(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Skip next instruction if key with the value of {} is pressed",
         //    self.v[x as usize]);
 
-        if self.keys[self.v[x] as usize] {
-            return InstructionOrd::Skip;
+        if self.key_down(self.v[x]) {
+            return Ok(InstructionOrd::Skip);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_exa1(&mut self, x: usize) -> InstructionOrd {
+    fn i_exa1(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Skip next instruction if key with the value of {} is not pressed",
         //    self.v[x as usize]);
 
-        if !self.keys[self.v[x] as usize] {
-            return InstructionOrd::Skip;
+        if !self.key_down(self.v[x]) {
+            return Ok(InstructionOrd::Skip);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx07(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx07(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set V[0x{:x}] = delay timer value: ({})",
         //    x,
         //    self.dt);
 
         self.v[x] = self.dt;
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx0a(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx0a(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Store in V[{}]", x);
         
         self.key_waiting = true;
         self.key_to_store = Some(x);
-        InstructionOrd::Next
+        self.key_held = None;
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx15(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx15(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set delay timer = V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize]);
 
         self.dt = self.v[x];
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx18(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx18(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set sound timer = V[0x{:x}]: ({})",
         //    x,
         //    self.v[x as usize]);
 
         self.st = self.v[x];
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx1e(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx1e(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("Set I = I:(0x{:x}) + V[0x{:x}]: ({})",
         //    self.i,
         //    x,
         //    self.v[x as usize]);
 
-        self.i += self.v[x] as u16;
-        InstructionOrd::Next
+        self.i = self.i.wrapping_add(self.v[x] as u16);
+        if self.quirks.fx1e_overflow_vf {
+            self.v[0xf] = (self.i > 0xfff) as u8;
+        }
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx29(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx29(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("I = address of memory location digit {}", self.v[x]);
         
-        self.i = (self.v[x]*5) as u16;
-        InstructionOrd::Next
+        // only the low nibble selects a digit, as on the COSMAC VIP
+        self.i = (self.v[x] & 0xf) as u16 * 5;
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx33(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx33(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         //println!("hundreds {}, tens {}, ones {}", self.v[x] / 100, self.v[x] / 10 % 10, self.v[x] % 10);
        
-        self.mem[self.i as usize] = self.v[x] / 100;
-        self.mem[self.i as usize+1] = self.v[x] / 10 % 10;
-        self.mem[self.i as usize+2] = self.v[x] % 10;
-        InstructionOrd::Next
+        self.write_mem(self.i as usize, self.v[x] / 100)?;
+        self.write_mem(self.i as usize+1, self.v[x] / 10 % 10)?;
+        self.write_mem(self.i as usize+2, self.v[x] % 10)?;
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx55(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx55(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         for i in 0..x + 1 {
-            self.mem[self.i as usize+i] = self.v[i];
+            self.write_mem(self.i as usize+i, self.v[i])?;
+        }
+        if self.quirks.load_store_increment_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
         }
-        InstructionOrd::Next
+        Ok(InstructionOrd::Next)
     }
 
-    fn i_fx65(&mut self, x: usize) -> InstructionOrd {
+    fn i_fx65(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
         for i in 0..x + 1 {
-            self.v[i] = self.mem[self.i as usize+i];
+            self.v[i] = self.read_mem(self.i as usize+i)?;
+        }
+        if self.quirks.load_store_increment_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        }
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_fx30(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
+        //println!("I = address of memory location big digit {}", self.v[x]);
+
+        self.i = BIG_FONT_ADDR + (self.v[x] & 0xf) as u16 * 10;
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_fx75(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
+        self.rpl[..x + 1].copy_from_slice(&self.v[..x + 1]);
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_fx85(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
+        self.v[..x + 1].copy_from_slice(&self.rpl[..x + 1]);
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_f000(&mut self, nnnn: u16) -> Result<InstructionOrd, Fault> {
+        self.i = nnnn;
+
+        //println!("Set I = 0x{:x}", self.i);
+
+        Ok(InstructionOrd::Skip)
+    }
+
+    fn i_fn01(&mut self, n: usize) -> Result<InstructionOrd, Fault> {
+        //println!("Select planes 0b{:b}", n);
+
+        self.planes = n as u8 & ((1 << PLANE_COUNT) - 1);
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_f002(&mut self) -> Result<InstructionOrd, Fault> {
+        for i in 0..AUDIO_PATTERN_SIZE {
+            self.pattern[i] = self.read_mem(self.i as usize + i)?;
+        }
+        Ok(InstructionOrd::Next)
+    }
+
+    fn i_fx3a(&mut self, x: usize) -> Result<InstructionOrd, Fault> {
+        //println!("Set pitch = V[0x{:x}]: ({})", x, self.v[x]);
+
+        self.pitch = self.v[x];
+        Ok(InstructionOrd::Next)
+    }
+}
+
+// registers Vx through Vy, counting down when x > y
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_asm;
+
+    fn cpu(rom: Vec<u8>) -> Cpu {
+        Cpu::new(rom, Quirks::MODERN).unwrap()
+    }
+
+    // keypad after a poll that pressed or released `key`
+    fn poll(keys: &mut Keypad, key: u8, down: bool) -> Keypad {
+        keys.begin_poll();
+        if down {
+            keys.press(key);
+        } else {
+            keys.release(key);
+        }
+        *keys
+    }
+
+    #[test]
+    fn unknown_opcodes_fault() {
+        let mut cpu = cpu(vec![0x50, 0x01]);
+        let e = cpu.tick(Keypad::new()).unwrap_err();
+        assert_eq!(e, Chip8Error { pc: 0x200, opcode: 0x5001, fault: Fault::UnknownOpcode });
+        assert_eq!(e.to_string(), "unknown opcode (pc: 0x200, opcode: 0x5001)");
+    }
+
+    #[test]
+    fn call_with_a_full_stack_faults() {
+        let mut cpu = cpu(chip8_asm!["main:", "    CALL main"]);
+        // slot 0 is never used, sp 0 is the empty stack
+        for depth in 1..STACK_SIZE {
+            cpu.tick(Keypad::new()).unwrap();
+            assert_eq!(cpu.sp() as usize, depth);
         }
-        InstructionOrd::Next
+        let e = cpu.tick(Keypad::new()).unwrap_err();
+        assert_eq!(e, Chip8Error { pc: 0x200, opcode: 0x2200, fault: Fault::StackOverflow });
+    }
+
+    #[test]
+    fn return_with_an_empty_stack_faults() {
+        let mut cpu = cpu(chip8_asm!["CALL sub", "RET", "sub:", "RET"]);
+        cpu.tick(Keypad::new()).unwrap();
+        cpu.tick(Keypad::new()).unwrap();
+        assert_eq!(cpu.pc(), 0x202);
+        let e = cpu.tick(Keypad::new()).unwrap_err();
+        assert_eq!(e, Chip8Error { pc: 0x202, opcode: 0x00ee, fault: Fault::StackUnderflow });
+    }
+
+    #[test]
+    fn stack_pointer_stays_on_the_stack() {
+        let mut cpu = cpu(chip8_asm!["CALL sub", "sub:", "RET"]);
+        cpu.tick(Keypad::new()).unwrap();
+        assert_eq!(cpu.set_sp(STACK_SIZE as u8), Err(Fault::StackOverflow));
+        assert_eq!(cpu.set_sp(0xff), Err(Fault::StackOverflow));
+        assert_eq!(cpu.call_stack(), [0x202]);
+
+        cpu.set_sp(STACK_SIZE as u8 - 1).unwrap();
+        assert_eq!(cpu.call_stack().len(), STACK_SIZE - 1);
+    }
+
+    #[test]
+    fn memory_ends_with_the_platform() {
+        let rom = chip8_asm!["LD I, 0xFFF", "LD V0, [I]", "LD V1, [I]", "LD V0, 1", "ADD I, V0", "LD V0, [I]"];
+        let mut cpu = cpu(rom.clone());
+        for _ in 0..2 {
+            cpu.tick(Keypad::new()).unwrap();
+        }
+        let e = cpu.tick(Keypad::new()).unwrap_err();
+        assert_eq!(e, Chip8Error { pc: 0x204, opcode: 0xf165, fault: Fault::MemoryOutOfBounds(0x1000) });
+
+        let mut xo = Cpu::new(rom, Quirks::XO_CHIP).unwrap();
+        xo.run_frame(Keypad::new(), 6).unwrap();
+        assert_eq!(xo.pc(), 0x20c);
+
+        let mut jumper = Cpu::new(chip8_asm!["JP 0xFFE"], Quirks::MODERN).unwrap();
+        jumper.memory_mut()[0xffe..0x1000].copy_from_slice(&[0x70, 0x01]);
+        jumper.tick(Keypad::new()).unwrap();
+        jumper.tick(Keypad::new()).unwrap();
+        let e = jumper.tick(Keypad::new()).unwrap_err();
+        assert_eq!((e.pc, e.fault), (0x1000, Fault::PcOutOfBounds));
+    }
+
+    #[test]
+    fn programs_fit_the_platform() {
+        assert!(Cpu::fits(&[0; 0xe00], 0x200, Platform::Chip8));
+        assert!(!Cpu::fits(&[0; 0xe01], 0x200, Platform::Chip8));
+        assert!(!Cpu::fits(&[0; 1], 0x1000, Platform::SuperChip));
+        assert!(Cpu::fits(&[0; 0xfe00], 0x200, Platform::XoChip));
+        assert!(!Cpu::fits(&[0; 0xfe01], 0x200, Platform::XoChip));
+    }
+
+    #[test]
+    fn sound_pattern_only_on_xo_chip() {
+        let rom = chip8_asm!["LD I, pattern", "AUDIO", "LD V0, 112", "PITCH V0", "end:", "JP end",
+            "pattern:", "DB 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0",
+            "DB 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0, 0xF0"];
+        let mut xo = Cpu::new(rom.clone(), Quirks::XO_CHIP).unwrap();
+        assert_eq!(xo.sound_pattern(), None);
+        xo.run_frame(Keypad::new(), 2).unwrap();
+        assert_eq!(xo.sound_pattern(), Some(([0xf0; AUDIO_PATTERN_SIZE], 4000.0)));
+        xo.run_frame(Keypad::new(), 2).unwrap();
+        assert_eq!(xo.sound_pattern().map(|(_, rate)| rate), Some(8000.0));
+
+        let mut cpu = cpu(rom);
+        cpu.run_frame(Keypad::new(), 4).unwrap();
+        assert_eq!(cpu.sound_pattern(), None);
+    }
+
+    #[test]
+    fn timers_run_while_waiting_for_a_key() {
+        let mut cpu = cpu(chip8_asm!["LD V0, 10", "LD DT, V0", "LD ST, V0", "LD V1, K"]);
+        for _ in 0..4 {
+            cpu.tick(Keypad::new()).unwrap();
+        }
+        assert!(cpu.waiting_for_key());
+        for _ in 0..4 {
+            cpu.run_frame(Keypad::new(), 10).unwrap();
+        }
+        assert!(cpu.waiting_for_key());
+        assert_eq!(cpu.delay_timer(), 6);
+        assert_eq!(cpu.sound_timer(), 6);
+    }
+
+    #[test]
+    fn programs_must_fit() {
+        assert_eq!(Cpu::new(Vec::new(), Quirks::MODERN).err(), Some(LoadError::Empty));
+        let rom = vec![0; 0xe01];
+        assert_eq!(Cpu::with_load_addr(rom, Quirks::MODERN, 0x200).err(),
+            Some(LoadError::TooLarge { len: 0xe01, load_addr: 0x200 }));
+        assert!(Cpu::with_load_addr(vec![0; 0xe01], Quirks::XO_CHIP, 0x200).is_ok());
+    }
+
+    #[test]
+    fn wait_key_finishes_when_the_key_goes_up() {
+        let mut cpu = cpu(chip8_asm!["LD V3, K", "LD V0, 1"]);
+        let mut keys = Keypad::new();
+
+        cpu.tick(keys).unwrap();
+        assert!(cpu.waiting_for_key());
+        for _ in 0..3 {
+            cpu.tick(keys).unwrap();
+        }
+        assert!(cpu.waiting_for_key());
+
+        cpu.tick(poll(&mut keys, 0xa, true)).unwrap();
+        cpu.tick(keys).unwrap();
+        assert!(cpu.waiting_for_key());
+        assert_eq!(cpu.pc(), 0x202);
+
+        cpu.tick(poll(&mut keys, 0xa, false)).unwrap();
+        assert!(!cpu.waiting_for_key());
+        assert_eq!(cpu.v()[3], 0xa);
+        cpu.tick(keys).unwrap();
+        assert_eq!(cpu.v()[0], 1);
+    }
+
+    #[test]
+    fn wait_key_catches_a_tap_within_one_poll() {
+        let mut cpu = cpu(chip8_asm!["LD V0, K"]);
+        let mut keys = Keypad::new();
+        cpu.tick(keys).unwrap();
+
+        keys.begin_poll();
+        keys.press(7);
+        keys.release(7);
+        cpu.tick(keys).unwrap();
+        assert!(!cpu.waiting_for_key());
+        assert_eq!(cpu.v()[0], 7);
     }
 }
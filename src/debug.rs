@@ -0,0 +1,223 @@
+/*
+ * Hooks into instruction execution, and the breakpoints the debugger
+ * console and the GDB stub are built on.
+ *
+ * Cpu::tick_with and Cpu::run_frame_with hand every instruction to an
+ * ExecHook right before it runs and right after it ran. The hook can
+ * stop the instruction from running, the CPU is then left exactly as it
+ * was before it.
+ */
+
+use std::fmt;
+use std::ops::ControlFlow;
+
+use crate::cpu::{Cpu, InstructionOrd};
+use crate::instruction::Instruction;
+
+pub trait ExecHook {
+    // `instruction` at cpu.pc() is about to run, Break leaves it unexecuted
+    fn before(&mut self, _cpu: &Cpu, _instruction: &Instruction) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+
+    // `instruction` ran without a fault, the CPU is in its state after it
+    // and `ord` is where it sent the program
+    fn after(&mut self, _cpu: &Cpu, _instruction: &Instruction, _ord: InstructionOrd) {}
+}
+
+// the hook that does nothing
+impl ExecHook for () {}
+
+impl<T: ExecHook + ?Sized> ExecHook for &mut T {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        (**self).before(cpu, instruction)
+    }
+
+    fn after(&mut self, cpu: &Cpu, instruction: &Instruction, ord: InstructionOrd) {
+        (**self).after(cpu, instruction, ord)
+    }
+}
+
+impl<T: ExecHook> ExecHook for Option<T> {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        match self {
+            Some(hook) => hook.before(cpu, instruction),
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    fn after(&mut self, cpu: &Cpu, instruction: &Instruction, ord: InstructionOrd) {
+        if let Some(hook) = self {
+            hook.after(cpu, instruction, ord);
+        }
+    }
+}
+
+// Both hooks, the first one first. When it breaks the second one doesn't
+// see the instruction at all.
+impl<A: ExecHook, B: ExecHook> ExecHook for (A, B) {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        self.0.before(cpu, instruction)?;
+        self.1.before(cpu, instruction)
+    }
+
+    fn after(&mut self, cpu: &Cpu, instruction: &Instruction, ord: InstructionOrd) {
+        self.0.after(cpu, instruction, ord);
+        self.1.after(cpu, instruction, ord);
+    }
+}
+
+// memory an instruction is going to read or write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: usize,
+    pub len: usize,
+    pub write: bool,
+}
+
+impl MemAccess {
+    pub fn overlaps(&self, addr: usize, len: usize) -> bool {
+        self.addr < addr + len && addr < self.addr + self.len
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            Watch::Read => !write,
+            Watch::Write => write,
+            Watch::Access => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    // instruction at this address is about to run
+    Pc(u16),
+    // opcode with all bits in `mask` equal to `value`
+    Opcode { value: u16, mask: u16 },
+    // instruction is about to read and/or write memory in addr..addr+len
+    Memory { addr: u16, len: u16, watch: Watch },
+}
+
+impl Breakpoint {
+    // Opcode pattern like "D01F" or "Dxyn": hex digits have to match,
+    // any other letter matches any nibble.
+    pub fn opcode(pattern: &str) -> Option<Breakpoint> {
+        if pattern.len() != 4 || !pattern.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        let mut value = 0;
+        let mut mask = 0;
+        for c in pattern.chars() {
+            value <<= 4;
+            mask <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                value |= digit as u16;
+                mask |= 0xf;
+            }
+        }
+        Some(Breakpoint::Opcode { value, mask })
+    }
+
+    pub fn hit(&self, cpu: &Cpu, instruction: &Instruction) -> bool {
+        match *self {
+            Breakpoint::Pc(addr) => cpu.pc() == addr,
+            Breakpoint::Opcode { value, mask } => instruction.encode() & mask == value,
+            Breakpoint::Memory { addr, len, watch } => match cpu.memory_access(instruction) {
+                Some(access) => watch.matches(access.write)
+                    && access.overlaps(addr as usize, len as usize),
+                None => false,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Breakpoint::Pc(addr) => write!(f, "pc 0x{:03x}", addr),
+            Breakpoint::Opcode { value, mask } => {
+                write!(f, "opcode ")?;
+                for shift in [12, 8, 4, 0] {
+                    if (mask >> shift) & 0xf == 0 {
+                        write!(f, "x")?;
+                    } else {
+                        write!(f, "{:X}", (value >> shift) & 0xf)?;
+                    }
+                }
+                Ok(())
+            },
+            Breakpoint::Memory { addr, len, watch } => {
+                let kind = match watch {
+                    Watch::Read => "read",
+                    Watch::Write => "write",
+                    Watch::Access => "access",
+                };
+                if len == 1 {
+                    write!(f, "{} 0x{:03x}", kind, addr)
+                } else {
+                    write!(f, "{} 0x{:03x}..0x{:03x}", kind, addr, addr as usize + len as usize)
+                }
+            },
+        }
+    }
+}
+
+// breakpoints numbered in the order they were set
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    list: Vec<(usize, Breakpoint)>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Breakpoints::default()
+    }
+
+    // returns the number of the new breakpoint
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        self.next_id += 1;
+        self.list.push((self.next_id, breakpoint));
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|(i, _)| *i != id);
+        self.list.len() != len
+    }
+
+    // removes every breakpoint equal to `breakpoint`
+    pub fn remove_all(&mut self, breakpoint: &Breakpoint) -> bool {
+        let len = self.list.len();
+        self.list.retain(|(_, b)| b != breakpoint);
+        self.list.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(usize, Breakpoint)> {
+        self.list.iter()
+    }
+
+    // first breakpoint hit by `instruction`
+    pub fn hit(&self, cpu: &Cpu, instruction: &Instruction) -> Option<(usize, Breakpoint)> {
+        self.list.iter().find(|(_, b)| b.hit(cpu, instruction)).copied()
+    }
+}
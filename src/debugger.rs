@@ -0,0 +1,466 @@
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+
+use chip_8::consts::RAM_SIZE;
+use chip_8::cpu::Cpu;
+use chip_8::debug::{Breakpoint, Breakpoints, ExecHook, Watch};
+use chip_8::error::Chip8Error;
+use chip_8::gdb::{GdbStub, Resume};
+use chip_8::instruction::Instruction;
+
+use crate::args::parse_number;
+
+/*
+ * Debugger console on stdin/stdout. The program stops in it when it hits
+ * a breakpoint, after a step, right at the start with --debug and when
+ * F11 is pressed. Type "help" there for the commands, an empty line
+ * repeats the previous one.
+ *
+ * With --gdb a GDB stub takes the console's place until the debugger
+ * detaches.
+*/
+
+const HELP: &str = "\
+c, continue            run until the next breakpoint
+s, step [N]            run N instructions (default 1)
+n, next                like step, but runs a CALL until it returns
+fin, finish            run until the current subroutine returns
+b, break ADDR          stop before the instruction at ADDR runs
+b, break op PATTERN    stop before opcodes matching PATTERN, e.g. Dxyn or 00EE
+watch ADDR [LEN]       stop before memory at ADDR is written
+rwatch ADDR [LEN]      stop before memory at ADDR is read
+awatch ADDR [LEN]      stop before memory at ADDR is read or written
+breaks                 list breakpoints
+d, delete [N]          delete breakpoint N, or all of them
+r, regs                show the registers and timers
+set REG VALUE          set v0-vf, i, pc, sp, dt or st
+x ADDR [LEN]           show LEN bytes of memory (default 64)
+poke ADDR BYTE...      write bytes to memory
+dis [ADDR] [N]         disassemble N instructions (default around pc)
+bt, stack              show the call stack
+q, quit                quit the emulator
+numbers are decimal, or hex with a 0x prefix";
+
+enum Mode {
+    Run,
+    // stop before the next instruction
+    Pause,
+    // stop after running this many more instructions
+    Step(u32),
+    // stop when a CALL made with `sp` frames on the stack returns to `addr`
+    StepOver { sp: u8, addr: u16 },
+    // stop once the stack has fewer than `sp` frames
+    Finish { sp: u8 },
+}
+
+pub struct Debugger {
+    breakpoints: Breakpoints,
+    mode: Mode,
+    // breakpoints at the instruction the program stopped at don't fire
+    // again right after it resumes
+    resumed: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Breakpoints::new(),
+            mode: Mode::Run,
+            resumed: false,
+            last_command: String::new(),
+        }
+    }
+
+    // Reads commands until one resumes the program. Break means quit,
+    // which is also what the end of input does.
+    pub fn prompt(&mut self, cpu: &mut Cpu) -> ControlFlow<()> {
+        println!("{}", location(cpu));
+        loop {
+            print!("(chip8) ");
+            let _ = io::stdout().flush();
+
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => return ControlFlow::Break(()),
+                Ok(_) => (),
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            match self.command(cpu, &line) {
+                Ok(Some(flow)) => {
+                    self.resumed = true;
+                    return flow;
+                },
+                Ok(None) => (),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    // Some when the command resumes the program
+    fn command(&mut self, cpu: &mut Cpu, line: &str) -> Result<Option<ControlFlow<()>>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (*name, args),
+            None => return Ok(None),
+        };
+
+        match name {
+            "c" | "continue" => self.mode = Mode::Run,
+            "s" | "step" => {
+                let n = match args.first() {
+                    Some(n) => number(n, u32::MAX as u64)? as u32,
+                    None => 1,
+                };
+                if n == 0 {
+                    return Err("nothing to step".to_string());
+                }
+                self.mode = Mode::Step(n);
+            },
+            "n" | "next" => {
+                self.mode = match cpu.current_instruction() {
+                    Ok(Instruction::Call(_)) => Mode::StepOver {
+                        sp: cpu.sp(),
+                        addr: cpu.pc().wrapping_add(2),
+                    },
+                    _ => Mode::Step(1),
+                };
+            },
+            "fin" | "finish" => {
+                if cpu.sp() == 0 {
+                    return Err("not in a subroutine".to_string());
+                }
+                self.mode = Mode::Finish { sp: cpu.sp() };
+            },
+            "b" | "break" => {
+                let breakpoint = match args {
+                    ["op", pattern] => match Breakpoint::opcode(pattern) {
+                        Some(breakpoint) => breakpoint,
+                        None => return Err(format!("invalid opcode pattern {}", pattern)),
+                    },
+                    [addr] => Breakpoint::Pc(address(addr)?),
+                    _ => return Err("usage: break ADDR | break op PATTERN".to_string()),
+                };
+                self.add_breakpoint(breakpoint);
+                return Ok(None);
+            },
+            "watch" | "rwatch" | "awatch" => {
+                let watch = match name {
+                    "rwatch" => Watch::Read,
+                    "awatch" => Watch::Access,
+                    _ => Watch::Write,
+                };
+                let (addr, len) = match args {
+                    [addr] => (address(addr)?, 1),
+                    [addr, len] => (address(addr)?, number(len, u16::MAX as u64)? as u16),
+                    _ => return Err(format!("usage: {} ADDR [LEN]", name)),
+                };
+                if len == 0 {
+                    return Err("nothing to watch".to_string());
+                }
+                self.add_breakpoint(Breakpoint::Memory { addr, len, watch });
+                return Ok(None);
+            },
+            "breaks" => {
+                if self.breakpoints.is_empty() {
+                    println!("No breakpoints");
+                }
+                for (id, breakpoint) in self.breakpoints.iter() {
+                    println!("{:3}  {}", id, breakpoint);
+                }
+                return Ok(None);
+            },
+            "d" | "delete" => {
+                match args.first() {
+                    Some(id) => {
+                        let id = number(id, usize::MAX as u64)? as usize;
+                        if !self.breakpoints.remove(id) {
+                            return Err(format!("no breakpoint {}", id));
+                        }
+                    },
+                    None => self.breakpoints.clear(),
+                }
+                return Ok(None);
+            },
+            "r" | "regs" => {
+                print_registers(cpu);
+                return Ok(None);
+            },
+            "set" => {
+                match args {
+                    [reg, value] => set_register(cpu, reg, value)?,
+                    _ => return Err("usage: set REG VALUE".to_string()),
+                }
+                return Ok(None);
+            },
+            "x" => {
+                let (addr, len) = match args {
+                    [addr] => (address(addr)? as usize, 64),
+                    [addr, len] => (address(addr)? as usize, number(len, RAM_SIZE as u64)? as usize),
+                    _ => return Err("usage: x ADDR [LEN]".to_string()),
+                };
+                print_memory(cpu, addr, len.min(RAM_SIZE - addr));
+                return Ok(None);
+            },
+            "poke" => {
+                let (addr, bytes) = match args.split_first() {
+                    Some((addr, bytes)) if !bytes.is_empty() => (address(addr)? as usize, bytes),
+                    _ => return Err("usage: poke ADDR BYTE...".to_string()),
+                };
+                if addr + bytes.len() > RAM_SIZE {
+                    return Err("past the end of memory".to_string());
+                }
+                let bytes = bytes.iter()
+                    .map(|b| number(b, 0xff).map(|b| b as u8))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                cpu.memory_mut()[addr..addr + bytes.len()].copy_from_slice(&bytes);
+                return Ok(None);
+            },
+            "dis" => {
+                let (addr, n) = match args {
+                    [] => (cpu.pc().saturating_sub(8), 10),
+                    [addr] => (address(addr)?, 10),
+                    [addr, n] => (address(addr)?, number(n, RAM_SIZE as u64)? as usize),
+                    _ => return Err("usage: dis [ADDR] [N]".to_string()),
+                };
+                self.print_disassembly(cpu, addr, n);
+                return Ok(None);
+            },
+            "bt" | "stack" => {
+                print_call_stack(cpu);
+                return Ok(None);
+            },
+            "h" | "help" => {
+                println!("{}", HELP);
+                return Ok(None);
+            },
+            "q" | "quit" => return Ok(Some(ControlFlow::Break(()))),
+            _ => return Err(format!("unknown command {}, try help", name)),
+        }
+        Ok(Some(ControlFlow::Continue(())))
+    }
+
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        let id = self.breakpoints.add(breakpoint);
+        println!("Breakpoint {}: {}", id, breakpoint);
+    }
+
+    fn print_disassembly(&self, cpu: &Cpu, addr: u16, n: usize) {
+        let mem = cpu.memory();
+        let mut addr = addr as usize;
+        for _ in 0..n {
+            if addr + 1 >= RAM_SIZE {
+                break;
+            }
+            let marker = if addr == cpu.pc() as usize { "=>" } else { "  " };
+            let bp = self.breakpoints.iter()
+                .any(|(_, b)| *b == Breakpoint::Pc(addr as u16));
+            let (text, size) = match Instruction::decode_at(mem, addr) {
+                Ok(instruction) => (instruction.to_string(), instruction.size() as usize),
+                Err(_) => ("???".to_string(), 2),
+            };
+            let bytes: Vec<String> = mem[addr..(addr + size).min(RAM_SIZE)].iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            println!("{}{} 0x{:03x}: {:9} {}", marker, if bp { "*" } else { " " },
+                addr, bytes.join(""), text);
+            addr += size;
+        }
+    }
+}
+
+impl ExecHook for Debugger {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        let resumed = std::mem::take(&mut self.resumed);
+        let stop = match self.mode {
+            Mode::Run => false,
+            Mode::Pause | Mode::Step(0) => true,
+            Mode::Step(n) => {
+                self.mode = Mode::Step(n - 1);
+                false
+            },
+            Mode::StepOver { sp, addr } => cpu.sp() == sp && cpu.pc() == addr,
+            Mode::Finish { sp } => cpu.sp() < sp,
+        };
+        if stop {
+            self.mode = Mode::Pause;
+            return ControlFlow::Break(());
+        }
+
+        if resumed || self.breakpoints.hit(cpu, instruction).is_none() {
+            return ControlFlow::Continue(());
+        }
+        for (id, breakpoint) in self.breakpoints.iter().filter(|(_, b)| b.hit(cpu, instruction)) {
+            println!("Breakpoint {}: {}", id, breakpoint);
+        }
+        self.mode = Mode::Pause;
+        ControlFlow::Break(())
+    }
+}
+
+// what the program stops in
+pub enum Monitor {
+    Console(Debugger),
+    Gdb(GdbStub),
+}
+
+impl Monitor {
+    pub fn hook(&mut self) -> &mut dyn ExecHook {
+        match self {
+            Monitor::Console(debugger) => debugger,
+            Monitor::Gdb(gdb) => gdb,
+        }
+    }
+
+    // the user wants to stop the program, call `stopped` next
+    pub fn interrupt(&mut self) {
+        if let Monitor::Gdb(gdb) = self {
+            gdb.interrupt();
+        }
+    }
+
+    // true when GDB asked to stop the program, call `stopped` next
+    pub fn interrupted(&mut self) -> bool {
+        let result = match self {
+            Monitor::Console(_) => return false,
+            Monitor::Gdb(gdb) => gdb.interrupted(),
+        };
+        result.unwrap_or_else(|e| {
+            self.lost(e);
+            false
+        })
+    }
+
+    // Hands over control until the program should go on. Break means
+    // quit.
+    pub fn stopped(&mut self, cpu: &mut Cpu) -> ControlFlow<()> {
+        let result = match self {
+            Monitor::Console(debugger) => return debugger.prompt(cpu),
+            Monitor::Gdb(gdb) => gdb.stopped(cpu),
+        };
+        match result {
+            Ok(Resume::Continue) => (),
+            Ok(Resume::Detach) => {
+                println!("Debugger detached");
+                *self = Monitor::Console(Debugger::new());
+            },
+            Ok(Resume::Kill) => return ControlFlow::Break(()),
+            Err(e) => self.lost(e),
+        }
+        ControlFlow::Continue(())
+    }
+
+    // true when there is a debugger to look into the fault, call
+    // `stopped` next then
+    pub fn fault(&mut self, error: &Chip8Error) -> bool {
+        match self {
+            Monitor::Console(_) => false,
+            Monitor::Gdb(gdb) => {
+                gdb.fault(error);
+                true
+            },
+        }
+    }
+
+    // the program ran 00FD
+    pub fn exited(&mut self) {
+        let result = match self {
+            Monitor::Console(_) => return,
+            Monitor::Gdb(gdb) => gdb.exited(),
+        };
+        if let Err(e) = result {
+            self.lost(e);
+        }
+    }
+
+    // the GDB connection broke, keep running without it
+    fn lost(&mut self, e: io::Error) {
+        eprintln!("Lost the debugger connection: {}", e);
+        *self = Monitor::Console(Debugger::new());
+    }
+}
+
+// "0x204: 6003  LD V0, 0x03"
+fn location(cpu: &Cpu) -> String {
+    match cpu.current_instruction() {
+        Ok(instruction) => format!("0x{:03x}: {:04x}  {}",
+            cpu.pc(), instruction.encode(), instruction),
+        Err(e) => format!("0x{:03x}: {}", cpu.pc(), e.fault),
+    }
+}
+
+fn print_registers(cpu: &Cpu) {
+    for (row, v) in cpu.v().chunks(8).enumerate() {
+        let regs: Vec<String> = v.iter().enumerate()
+            .map(|(i, v)| format!("V{:X} {:02x}", row * 8 + i, v))
+            .collect();
+        println!("{}", regs.join("  "));
+    }
+    println!("I 0x{:03x}  PC 0x{:03x}  SP {}  DT {}  ST {}  cycle {}{}",
+        cpu.i(), cpu.pc(), cpu.sp(), cpu.delay_timer(), cpu.sound_timer(), cpu.cycle(),
+        if cpu.waiting_for_key() { "  waiting for a key" } else { "" });
+}
+
+fn set_register(cpu: &mut Cpu, reg: &str, value: &str) -> Result<(), String> {
+    let reg = reg.to_ascii_lowercase();
+    match reg.as_str() {
+        "i" => cpu.set_i(number(value, 0xffff)? as u16),
+        "pc" => cpu.set_pc(address(value)?),
+        "sp" => cpu.set_sp(number(value, 0xff)? as u8).map_err(|e| format!("sp {}: {}", value, e))?,
+        "dt" => cpu.set_delay_timer(number(value, 0xff)? as u8),
+        "st" => cpu.set_sound_timer(number(value, 0xff)? as u8),
+        _ => {
+            let x = reg.strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| usize::from_str_radix(x, 16).ok());
+            match x {
+                Some(x) => cpu.set_v(x, number(value, 0xff)? as u8),
+                None => return Err(format!("unknown register {}", reg)),
+            }
+        },
+    }
+    Ok(())
+}
+
+// 16 bytes a line with the printable ones on the right
+fn print_memory(cpu: &Cpu, addr: usize, len: usize) {
+    let mem = &cpu.memory()[addr..addr + len];
+    for (row, bytes) in mem.chunks(16).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let text: String = bytes.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        println!("0x{:04x}: {:47}  {}", addr + row * 16, hex.join(" "), text);
+    }
+}
+
+// innermost call first, each with the CALL it came from
+fn print_call_stack(cpu: &Cpu) {
+    println!("#0  0x{:03x}", cpu.pc());
+    for (depth, ret) in cpu.call_stack().iter().rev().enumerate() {
+        let call = ret.wrapping_sub(2);
+        match Instruction::decode_at(cpu.memory(), call as usize) {
+            Ok(instruction @ Instruction::Call(_)) =>
+                println!("#{}  0x{:03x}  {}", depth + 1, call, instruction),
+            _ => println!("#{}  0x{:03x}  returns to 0x{:03x}", depth + 1, call, ret),
+        }
+    }
+}
+
+fn address(value: &str) -> Result<u16, String> {
+    number(value, RAM_SIZE as u64 - 1).map(|addr| addr as u16)
+}
+
+// hex with a 0x prefix, decimal otherwise, at most `max`
+fn number(value: &str, max: u64) -> Result<u64, String> {
+    match parse_number(value) {
+        Some(n) if n <= max => Ok(n),
+        Some(_) => Err(format!("{} is too big, at most 0x{:x}", value, max)),
+        None => Err(format!("invalid number {}", value)),
+    }
+}
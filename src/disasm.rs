@@ -0,0 +1,325 @@
+/*
+ * Disassembler. Code is told apart from data by following every path the
+ * program can take from its entry point: jumps, calls, both sides of the
+ * skips and the base of BNNN tables. Whatever is never reached is data,
+ * and data that ANNN points at is laid out as sprite rows.
+ *
+ * Jump, call and ANNN targets get generated labels so the output can be
+ * fed back to an assembler.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::consts::START_ADDR;
+use crate::instruction::Instruction;
+
+// bytes per line of data that isn't a sprite
+const DATA_PER_LINE: usize = 8;
+// column the address and raw bytes comments start at
+const COMMENT_COLUMN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    // Octo, as used by most modern CHIP-8 programs
+    Octo,
+    // mnemonics from Cowgod's Chip-8 technical reference
+    Cowgod,
+}
+
+impl std::str::FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "octo" => Ok(Syntax::Octo),
+            "cowgod" | "mnemonic" => Ok(Syntax::Cowgod),
+            _ => Err(format!("unknown syntax: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    Code(Instruction),
+    Data,
+    // data that ANNN points at, one row of pixels per line
+    Sprite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub item: Item,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Target {
+    // ordered by how much they say about the address
+    Data,
+    Jump,
+    Sub,
+    Entry,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    // only addresses some line starts at have a label
+    pub labels: BTreeMap<u16, String>,
+}
+
+fn add_target(targets: &mut BTreeMap<u16, Target>, addr: u16, kind: Target) {
+    let old = targets.entry(addr).or_insert(kind);
+    *old = (*old).max(kind);
+}
+
+pub fn disassemble(rom: &[u8], load_addr: u16) -> Disassembly {
+    disassemble_from(rom, load_addr, &[])
+}
+
+// Like `disassemble`, with `entries` walked as well, for code that is only
+// reached in ways the walk can't follow, like computed jumps.
+pub fn disassemble_from(rom: &[u8], load_addr: u16, entries: &[u16]) -> Disassembly {
+    let end = load_addr as usize + rom.len();
+    let decode = |addr: u16| -> Option<Instruction> {
+        if (addr as usize) < load_addr as usize || addr as usize >= end {
+            return None;
+        }
+        Instruction::decode_at(rom, (addr - load_addr) as usize).ok()
+    };
+
+    // walk the program
+    let mut code = BTreeMap::new();
+    let mut targets = BTreeMap::new();
+    add_target(&mut targets, load_addr, Target::Entry);
+
+    let mut pending = vec![load_addr];
+    pending.extend_from_slice(entries);
+    while let Some(mut addr) = pending.pop() {
+        while let Some(instruction) = decode(addr) {
+            if code.insert(addr, instruction).is_some() {
+                break;
+            }
+            let next = addr.wrapping_add(instruction.size());
+            match instruction {
+                Instruction::Jump(nnn) => {
+                    add_target(&mut targets, nnn, Target::Jump);
+                    pending.push(nnn);
+                    break;
+                },
+                Instruction::JumpOffset(_, nnn) => {
+                    add_target(&mut targets, nnn, Target::Jump);
+                    pending.push(nnn);
+                    break;
+                },
+                Instruction::Call(nnn) => {
+                    add_target(&mut targets, nnn, Target::Sub);
+                    pending.push(nnn);
+                },
+                Instruction::Ret | Instruction::Exit => break,
+                Instruction::LoadI(nnn) => add_target(&mut targets, nnn, Target::Data),
+                Instruction::LoadILong(nnnn) => add_target(&mut targets, nnnn, Target::Data),
+                _ if instruction.is_skip() => {
+                    let skipped = decode(next).map_or(2, |i| i.size());
+                    pending.push(next.wrapping_add(skipped));
+                },
+                _ => (),
+            }
+            addr = next;
+        }
+    }
+
+    // lay out the lines
+    let mut lines = Vec::new();
+    let mut addr = load_addr as usize;
+    let mut sprite = false;
+    while addr < end {
+        let at = addr as u16;
+        if let Some(instruction) = code.get(&at) {
+            let size = instruction.size() as usize;
+            lines.push(Line {
+                addr: at,
+                bytes: rom[addr - load_addr as usize..(addr + size).min(end) - load_addr as usize].to_vec(),
+                item: Item::Code(*instruction),
+            });
+            addr += size;
+            sprite = false;
+            continue;
+        }
+
+        // data runs up to the next line somebody could refer to
+        if targets.get(&at) == Some(&Target::Data) {
+            sprite = true;
+        }
+        let per_line = if sprite { 1 } else { DATA_PER_LINE };
+        let mut len = 1;
+        while len < per_line && addr + len < end {
+            let next = (addr + len) as u16;
+            if code.contains_key(&next) || targets.contains_key(&next) {
+                break;
+            }
+            len += 1;
+        }
+        lines.push(Line {
+            addr: at,
+            bytes: rom[addr - load_addr as usize..addr + len - load_addr as usize].to_vec(),
+            item: if sprite { Item::Sprite } else { Item::Data },
+        });
+        addr += len;
+    }
+
+    let starts: BTreeSet<u16> = lines.iter().map(|l| l.addr).collect();
+    let labels = targets.into_iter()
+        .filter(|(addr, _)| starts.contains(addr))
+        .map(|(addr, kind)| {
+            let name = match kind {
+                Target::Entry => "main".to_string(),
+                Target::Sub => format!("sub_{:03x}", addr),
+                Target::Jump => format!("label_{:03x}", addr),
+                Target::Data => format!("data_{:03x}", addr),
+            };
+            (addr, name)
+        })
+        .collect();
+
+    Disassembly { lines, labels }
+}
+
+impl Disassembly {
+    // `listing` adds the address and raw bytes of every line as a comment
+    pub fn format(&self, syntax: Syntax, listing: bool) -> String {
+        let mut out = String::new();
+        match self.lines.first() {
+            Some(line) if line.addr != START_ADDR => match syntax {
+                Syntax::Octo => writeln!(out, ":org 0x{:03X}", line.addr).unwrap(),
+                Syntax::Cowgod => writeln!(out, "ORG 0x{:03X}", line.addr).unwrap(),
+            },
+            _ => (),
+        }
+        for line in self.lines.iter() {
+            if let Some(label) = self.labels.get(&line.addr) {
+                match syntax {
+                    Syntax::Octo => writeln!(out, ": {}", label).unwrap(),
+                    Syntax::Cowgod => writeln!(out, "{}:", label).unwrap(),
+                }
+            }
+
+            let text = match line.item {
+                Item::Code(instruction) => format_instruction(&instruction, syntax, &self.labels),
+                Item::Data | Item::Sprite => format_data(&line.bytes, syntax),
+            };
+            if !listing {
+                writeln!(out, "    {}", text).unwrap();
+                continue;
+            }
+
+            let raw: String = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let comment = match syntax {
+                Syntax::Octo => '#',
+                Syntax::Cowgod => ';',
+            };
+            let mut text = format!("    {:<width$} {} 0x{:03x}  {}",
+                text, comment, line.addr, raw, width = COMMENT_COLUMN - 5);
+            if line.item == Item::Sprite {
+                let pixels: String = (0..8)
+                    .map(|bit| if line.bytes[0] & (0x80 >> bit) != 0 { '#' } else { '.' })
+                    .collect();
+                write!(text, "  {}", pixels).unwrap();
+            }
+            writeln!(out, "{}", text.trim_end()).unwrap();
+        }
+        out
+    }
+}
+
+pub fn format_data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    match syntax {
+        Syntax::Octo => bytes.join(" "),
+        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+    }
+}
+
+// Formats one instruction, addresses that have a label in `labels` are
+// written as the label.
+pub fn format_instruction(instruction: &Instruction,
+                          syntax: Syntax,
+                          labels: &BTreeMap<u16, String>) -> String
+{
+    let target = |addr: u16, digits: usize| match labels.get(&addr) {
+        Some(label) => label.clone(),
+        None => format!("0x{:0width$X}", addr, width = digits),
+    };
+    match syntax {
+        Syntax::Cowgod => match *instruction {
+            Instruction::Jump(nnn) => format!("JP {}", target(nnn, 3)),
+            Instruction::Call(nnn) => format!("CALL {}", target(nnn, 3)),
+            Instruction::LoadI(nnn) => format!("LD I, {}", target(nnn, 3)),
+            Instruction::JumpOffset(_, nnn) => format!("JP V0, {}", target(nnn, 3)),
+            Instruction::LoadILong(nnnn) => format!("LD I, LONG {}", target(nnnn, 4)),
+            _ => instruction.to_string(),
+        },
+        Syntax::Octo => octo(instruction, target),
+    }
+}
+
+fn octo(instruction: &Instruction, target: impl Fn(u16, usize) -> String) -> String {
+    match *instruction {
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LoRes => "lores".to_string(),
+        Instruction::HiRes => "hires".to_string(),
+        // Octo has no machine code calls, keep the bytes
+        Instruction::Sys(nnn) => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xff),
+        Instruction::Jump(nnn) => format!("jump {}", target(nnn, 3)),
+        Instruction::Call(nnn) => format!(":call {}", target(nnn, 3)),
+        // Octo's conditions say when the next instruction runs, the opcodes
+        // say when it is skipped
+        Instruction::SkipEqByte(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+        Instruction::SkipNeByte(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+        Instruction::SkipEqReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SkipNeReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::SkipKey(x) => format!("if v{:x} -key then", x),
+        Instruction::SkipNotKey(x) => format!("if v{:x} key then", x),
+        Instruction::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LoadByte(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+        Instruction::AddByte(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+        Instruction::Move(x, y) => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::Add(x, y) => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Instruction::Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubN(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Instruction::Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::LoadI(nnn) => format!("i := {}", target(nnn, 3)),
+        Instruction::JumpOffset(_, nnn) => format!("jump0 {}", target(nnn, 3)),
+        Instruction::Random(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+        Instruction::Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::LoadILong(nnnn) => format!("i := long {}", target(nnnn, 4)),
+        Instruction::Plane(n) => format!("plane {}", n),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::LoadDelay(x) => format!("v{:x} := delay", x),
+        Instruction::WaitKey(x) => format!("v{:x} := key", x),
+        Instruction::SetDelay(x) => format!("delay := v{:x}", x),
+        Instruction::SetSound(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddI(x) => format!("i += v{:x}", x),
+        Instruction::Font(x) => format!("i := hex v{:x}", x),
+        Instruction::BigFont(x) => format!("i := bighex v{:x}", x),
+        Instruction::Bcd(x) => format!("bcd v{:x}", x),
+        Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+        Instruction::Store(x) => format!("save v{:x}", x),
+        Instruction::Load(x) => format!("load v{:x}", x),
+        Instruction::SaveFlags(x) => format!("saveflags v{:x}", x),
+        Instruction::LoadFlags(x) => format!("loadflags v{:x}", x),
+    }
+}
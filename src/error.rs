@@ -0,0 +1,66 @@
+use std::fmt;
+
+// what went wrong while executing a single instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // opcode (or one of its sub-opcodes) is not part of the instruction set
+    UnknownOpcode,
+    // 2NNN called with every stack slot already in use
+    StackOverflow,
+    // 00EE executed with an empty stack
+    StackUnderflow,
+    // instruction tried to read or write memory at this address
+    MemoryOutOfBounds(usize),
+    // program counter points outside of memory
+    PcOutOfBounds,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::UnknownOpcode => write!(f, "unknown opcode"),
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::MemoryOutOfBounds(addr) =>
+                write!(f, "memory access out of bounds at 0x{:x}", addr),
+            Fault::PcOutOfBounds => write!(f, "program counter out of bounds"),
+        }
+    }
+}
+
+// fault together with the machine state at the moment it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chip8Error {
+    pub pc: u16,
+    pub opcode: u16,
+    pub fault: Fault,
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (pc: 0x{:03x}, opcode: 0x{:04x})",
+            self.fault, self.pc, self.opcode)
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+// why a program can't be loaded into memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Empty,
+    // `len` bytes don't fit between `load_addr` and the end of memory
+    TooLarge { len: usize, load_addr: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "program is empty"),
+            LoadError::TooLarge { len, load_addr } =>
+                write!(f, "program of {} bytes doesn't fit in memory at 0x{:03x}", len, load_addr),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
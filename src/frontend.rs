@@ -0,0 +1,80 @@
+use crate::consts::*;
+use crate::keypad::Keypad;
+
+// emulator actions requested through a frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Quit,
+    SaveState(u8),
+    LoadState(u8),
+    ToggleMute,
+    // stop in the debugger console
+    Debug,
+}
+
+// Something that can show the framebuffer.
+pub trait DisplaySink {
+    // `vmem` as in Cpu::vmem, only the top left `cols`x`rows` corner of
+    // it is in use
+    fn draw(&mut self,
+            vmem: &[[u8; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH],
+            cols: usize,
+            rows: usize) -> Result<(), String>;
+}
+
+// Something that can beep.
+pub trait AudioSink {
+    // called once per frame, `active` while the sound timer is running
+    fn set_active(&mut self, active: bool);
+
+    fn set_muted(&mut self, _muted: bool) {}
+
+    // XO-CHIP pattern and its rate in Hz to play instead of the tone, see
+    // Cpu::sound_pattern
+    fn set_pattern(&mut self, _pattern: Option<([u8; AUDIO_PATTERN_SIZE], f32)>) {}
+
+    fn muted(&self) -> bool {
+        false
+    }
+}
+
+// Something that knows which of the 16 keys are held down.
+pub trait KeypadSource {
+    // handles pending host events and returns the keypad with the presses
+    // and releases that happened since the previous poll
+    fn poll(&mut self) -> Keypad;
+
+    // hotkeys pressed since the last call
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+
+    fn rewind_held(&self) -> bool {
+        false
+    }
+}
+
+// backends that do nothing, for running without any UI
+pub struct NullDisplay;
+pub struct NullAudio;
+pub struct NullKeypad;
+
+impl DisplaySink for NullDisplay {
+    fn draw(&mut self,
+            _: &[[u8; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH],
+            _: usize,
+            _: usize) -> Result<(), String>
+    {
+        Ok(())
+    }
+}
+
+impl AudioSink for NullAudio {
+    fn set_active(&mut self, _: bool) {}
+}
+
+impl KeypadSource for NullKeypad {
+    fn poll(&mut self) -> Keypad {
+        Keypad::new()
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use crate::config::GamepadConfig;
+use crate::keymap::parse_key;
+
+// two players is all any CHIP-8 game needs
+pub const PLAYERS: usize = 2;
+
+const AXES: usize = 6;
+const DEFAULT_DEADZONE: f32 = 0.3;
+
+// D-pad and left stick on 2/4/6/8, the keys most games move with
+const DEFAULT_PLAYER1: &[(&str, &[&str])] = &[
+    ("2", &["dpup", "lefty-"]),
+    ("4", &["dpleft", "leftx-"]),
+    ("6", &["dpright", "leftx+"]),
+    ("8", &["dpdown", "lefty+"]),
+    ("5", &["a"]),
+    ("0", &["b"]),
+];
+
+// the right hand column, where two player games like Pong put the
+// second player's up and down
+const DEFAULT_PLAYER2: &[(&str, &[&str])] = &[
+    ("C", &["dpup", "lefty-"]),
+    ("D", &["dpdown", "lefty+"]),
+    ("E", &["a"]),
+    ("F", &["b"]),
+];
+
+// Controller inputs of one player mapped to CHIP-8 keys.
+#[derive(Debug, Clone, Default)]
+pub struct PadProfile {
+    buttons: HashMap<Button, u8>,
+    // axis and direction, true for positive
+    axes: HashMap<(Axis, bool), u8>,
+}
+
+impl PadProfile {
+    // `bind` goes from CHIP-8 key to SDL button names ("a", "dpup", ...)
+    // and stick directions ("leftx-", "righty+", "lefttrigger", ...)
+    pub fn from_config(bind: &BTreeMap<String, Vec<String>>) -> Result<PadProfile, String> {
+        let mut profile = PadProfile::default();
+        for (key, names) in bind.iter() {
+            let key = match parse_key(key) {
+                Some(key) => key,
+                None => return Err(format!("{} is not a CHIP-8 key (0-F)", key)),
+            };
+            for name in names {
+                profile.bind(name, key)?;
+            }
+        }
+        Ok(profile)
+    }
+
+    fn from_layout(layout: &[(&str, &[&str])]) -> Result<PadProfile, String> {
+        let bind = layout.iter()
+            .map(|(key, names)| (key.to_string(), names.iter().map(|n| n.to_string()).collect()))
+            .collect();
+        PadProfile::from_config(&bind)
+    }
+
+    fn bind(&mut self, name: &str, key: u8) -> Result<(), String> {
+        let name = name.to_ascii_lowercase();
+        if let Some(button) = Button::from_string(&name) {
+            self.buttons.insert(button, key);
+            return Ok(());
+        }
+        let (axis, positive) = match name.strip_suffix('-') {
+            Some(axis) => (axis, false),
+            None => (name.strip_suffix('+').unwrap_or(&name), true),
+        };
+        match Axis::from_string(axis) {
+            Some(axis) => {
+                self.axes.insert((axis, positive), key);
+                Ok(())
+            },
+            None => Err(format!("unknown controller input {}", name)),
+        }
+    }
+}
+
+// Key layouts for both players plus how far sticks have to move to count.
+#[derive(Debug, Clone)]
+pub struct GamepadProfiles {
+    players: [PadProfile; PLAYERS],
+    deadzone: i16,
+}
+
+impl GamepadProfiles {
+    pub fn from_config(cfg: &GamepadConfig) -> Result<GamepadProfiles, String> {
+        let player1 = match &cfg.player1 {
+            Some(bind) => PadProfile::from_config(bind)?,
+            None => PadProfile::from_layout(DEFAULT_PLAYER1)?,
+        };
+        let player2 = match &cfg.player2 {
+            Some(bind) => PadProfile::from_config(bind)?,
+            None => PadProfile::from_layout(DEFAULT_PLAYER2)?,
+        };
+
+        let deadzone = cfg.deadzone.unwrap_or(DEFAULT_DEADZONE);
+        if !(0.0..1.0).contains(&deadzone) {
+            return Err(format!("deadzone {} is not between 0 and 1", deadzone));
+        }
+
+        Ok(GamepadProfiles {
+            players: [player1, player2],
+            deadzone: (deadzone * i16::MAX as f32) as i16,
+        })
+    }
+}
+
+// a button, or a stick pushed in one direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PadInput {
+    Button(Button),
+    Axis(Axis, bool),
+}
+
+struct Player {
+    controller: GameController,
+    // direction every axis is pushed in: -1, 0 or 1
+    axes: [i8; AXES],
+    // inputs this player holds down and the CHIP-8 key each one is bound
+    // to, the D-pad and a stick can both hold the same key
+    held: HashMap<PadInput, u8>,
+}
+
+impl Player {
+    fn press(&mut self, input: PadInput, key: u8) {
+        self.held.insert(input, key);
+    }
+
+    fn release(&mut self, input: PadInput) {
+        self.held.remove(&input);
+    }
+
+    fn keys(&self) -> u16 {
+        self.held.values().fold(0, |keys, key| keys | 1 << key)
+    }
+}
+
+// Open game controllers, the first two to connect are player 1 and 2.
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    profiles: GamepadProfiles,
+    players: [Option<Player>; PLAYERS],
+}
+
+impl Gamepads {
+    // controllers connected already show up as hotplug events on the
+    // first poll
+    pub fn new(ctx: &sdl2::Sdl, profiles: GamepadProfiles) -> Result<Self, String> {
+        Ok(Self {
+            subsystem: ctx.game_controller()?,
+            profiles,
+            players: [None, None],
+        })
+    }
+
+    // CHIP-8 keys any player holds down, one bit per key
+    pub fn held(&self) -> u16 {
+        self.players.iter().flatten().fold(0, |held, player| held | player.keys())
+    }
+
+    // applies a controller event to the held keys, false if `event` has
+    // nothing to do with controllers
+    pub fn handle(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => self.connect(which),
+            Event::ControllerDeviceRemoved { which, .. } => self.disconnect(which),
+            Event::ControllerButtonDown { which, button, .. } => {
+                if let Some((n, player)) = find_player(&mut self.players, which) {
+                    if let Some(&key) = self.profiles.players[n].buttons.get(&button) {
+                        player.press(PadInput::Button(button), key);
+                    }
+                }
+            },
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let Some((_, player)) = find_player(&mut self.players, which) {
+                    player.release(PadInput::Button(button));
+                }
+            },
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                let deadzone = self.profiles.deadzone;
+                let direction = if value > deadzone {
+                    1
+                } else if value < -deadzone {
+                    -1
+                } else {
+                    0
+                };
+                if let Some((n, player)) = find_player(&mut self.players, which) {
+                    let old = std::mem::replace(&mut player.axes[axis as usize], direction);
+                    if old == direction {
+                        return true;
+                    }
+                    let profile = &self.profiles.players[n];
+                    if old != 0 {
+                        player.release(PadInput::Axis(axis, old > 0));
+                    }
+                    if direction != 0 {
+                        if let Some(&key) = profile.axes.get(&(axis, direction > 0)) {
+                            player.press(PadInput::Axis(axis, direction > 0), key);
+                        }
+                    }
+                }
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn connect(&mut self, joystick_index: u32) {
+        let controller = match self.subsystem.open(joystick_index) {
+            Ok(controller) => controller,
+            Err(e) => {
+                eprintln!("Can't open controller {}: {}", joystick_index, e);
+                return;
+            },
+        };
+        let id = controller.instance_id();
+        if find_player(&mut self.players, id).is_some() {
+            return;
+        }
+        match self.players.iter().position(|p| p.is_none()) {
+            Some(n) => {
+                println!("{} connected as player {}", controller.name(), n + 1);
+                self.players[n] = Some(Player {
+                    controller,
+                    axes: [0; AXES],
+                    held: HashMap::new(),
+                });
+            },
+            None => println!("{} connected, but there are already {} players",
+                controller.name(), PLAYERS),
+        }
+    }
+
+    // whatever the player held goes up with it
+    fn disconnect(&mut self, id: u32) {
+        if let Some((n, _)) = find_player(&mut self.players, id) {
+            println!("Player {} disconnected", n + 1);
+            self.players[n] = None;
+        }
+    }
+}
+
+fn find_player(players: &mut [Option<Player>; PLAYERS], id: u32)
+    -> Option<(usize, &mut Player)>
+{
+    players.iter_mut()
+        .enumerate()
+        .find_map(|(n, p)| match p {
+            Some(p) if p.controller.instance_id() == id => Some((n, p)),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(layout: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        layout.iter()
+            .map(|(key, names)| (key.to_string(), names.iter().map(|n| n.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn both_players_have_a_default_layout() {
+        let profiles = GamepadProfiles::from_config(&GamepadConfig::default()).unwrap();
+        let [player1, player2] = &profiles.players;
+
+        assert_eq!(player1.buttons.get(&Button::DPadUp), Some(&0x2));
+        assert_eq!(player1.buttons.get(&Button::DPadRight), Some(&0x6));
+        assert_eq!(player1.buttons.get(&Button::A), Some(&0x5));
+        assert_eq!(player1.axes.get(&(Axis::LeftX, false)), Some(&0x4));
+        assert_eq!(player1.axes.get(&(Axis::LeftY, true)), Some(&0x8));
+
+        assert_eq!(player2.buttons.get(&Button::DPadUp), Some(&0xc));
+        assert_eq!(player2.buttons.get(&Button::DPadDown), Some(&0xd));
+        assert_eq!(player2.buttons.get(&Button::A), Some(&0xe));
+        assert_eq!(player2.buttons.get(&Button::B), Some(&0xf));
+        assert_eq!(player2.axes.get(&(Axis::LeftY, false)), Some(&0xc));
+        assert_eq!(player2.axes.get(&(Axis::LeftY, true)), Some(&0xd));
+    }
+
+    #[test]
+    fn config_replaces_a_whole_layout() {
+        let cfg = GamepadConfig {
+            deadzone: Some(0.5),
+            player1: None,
+            player2: Some(bind(&[("1", &["x", "righty-"])])),
+        };
+        let profiles = GamepadProfiles::from_config(&cfg).unwrap();
+        let [player1, player2] = &profiles.players;
+        assert_eq!(player1.buttons.len(), 6);
+        assert_eq!(player2.buttons, HashMap::from([(Button::X, 0x1)]));
+        assert_eq!(player2.axes, HashMap::from([((Axis::RightY, false), 0x1)]));
+        assert_eq!(profiles.deadzone, i16::MAX / 2);
+    }
+
+    #[test]
+    fn bad_bindings() {
+        let cfg = |bind| GamepadConfig { player1: Some(bind), ..GamepadConfig::default() };
+        assert!(GamepadProfiles::from_config(&cfg(bind(&[("G", &["a"])]))).is_err());
+        assert!(GamepadProfiles::from_config(&cfg(bind(&[("1", &["nope"])]))).is_err());
+        let deadzone = GamepadConfig { deadzone: Some(1.0), ..GamepadConfig::default() };
+        assert!(GamepadProfiles::from_config(&deadzone).is_err());
+    }
+}
@@ -0,0 +1,766 @@
+/*
+ * GDB remote serial protocol server. It waits for one debugger to
+ * connect over TCP or a Unix socket and serves it whenever the program
+ * is stopped, see GdbStub::stopped.
+ *
+ * The registers are described by target.xml: V0-VF, SP, DT and ST are
+ * 8 bits, I and PC 16 bits little endian, numbered in this order
+ *
+ *     0-15 v0-vf, 16 i, 17 pc, 18 sp, 19 dt, 20 st
+ *
+ * and memory is what the platform has, 64 KiB on XO-CHIP and 4 KiB
+ * elsewhere. Software and hardware breakpoints both stop before the
+ * instruction at their address runs, watchpoints stop right after the
+ * instruction that touched the memory.
+ */
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::ops::ControlFlow;
+
+use crate::consts::REGISTER_COUNT;
+use crate::cpu::Cpu;
+use crate::debug::{Breakpoint, Breakpoints, ExecHook, Watch};
+use crate::error::{Chip8Error, Fault};
+use crate::instruction::Instruction;
+
+const REGISTERS: usize = REGISTER_COUNT + 5;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// what the debugger asked for once the program is stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    // keep running under the debugger
+    Continue,
+    // the debugger went away, keep running without it
+    Detach,
+    // the debugger asked to kill the program
+    Kill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Signal(u8),
+    Breakpoint,
+    Watch(Watch, u16),
+    Exited(u8),
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+pub struct GdbStub {
+    conn: Connection,
+    no_ack: bool,
+    breakpoints: Breakpoints,
+    // instructions left to run before stopping, None while continuing
+    steps: Option<u32>,
+    // a c or s packet is waiting for its stop reply
+    running: bool,
+    stop: Stop,
+    // watchpoint hit by the instruction that is running now
+    watch_hit: Option<(Watch, u16)>,
+    // the breakpoint the program stopped at doesn't fire again right away
+    resumed: bool,
+}
+
+// Waits for a debugger to connect to `addr`, which is "PORT" for
+// localhost, "HOST:PORT" or "unix:PATH".
+pub fn listen(addr: &str) -> io::Result<GdbStub> {
+    let conn = match addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(path) => {
+            let (stream, _) = UnixListener::bind(path)?.accept()?;
+            // nobody else can connect anyway, don't leave the socket behind
+            let _ = fs::remove_file(path);
+            Connection::Unix(stream)
+        },
+        #[cfg(not(unix))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported,
+            "Unix sockets are not supported here")),
+        None => {
+            let addr = if addr.chars().all(|c| c.is_ascii_digit()) {
+                format!("127.0.0.1:{}", addr)
+            } else {
+                addr.to_string()
+            };
+            let (stream, _) = TcpListener::bind(addr)?.accept()?;
+            stream.set_nodelay(true)?;
+            Connection::Tcp(stream)
+        },
+    };
+    Ok(GdbStub {
+        conn,
+        no_ack: false,
+        breakpoints: Breakpoints::new(),
+        steps: None,
+        running: false,
+        stop: Stop::Signal(SIGTRAP),
+        watch_hit: None,
+        resumed: false,
+    })
+}
+
+impl GdbStub {
+    // True when the debugger sent an interrupt (Ctrl-C) while the
+    // program was running, call `stopped` then.
+    pub fn interrupted(&mut self) -> io::Result<bool> {
+        self.conn.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.conn.read(&mut byte);
+        self.conn.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == 0x03 => {
+                self.interrupt();
+                Ok(true)
+            },
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // stops the program as if the debugger interrupted it
+    pub fn interrupt(&mut self) {
+        self.stop = Stop::Signal(SIGINT);
+    }
+
+    // the program stopped at `error`
+    pub fn fault(&mut self, error: &Chip8Error) {
+        self.stop = Stop::Signal(match error.fault {
+            Fault::UnknownOpcode => SIGILL,
+            _ => SIGSEGV,
+        });
+    }
+
+    // the program ran 00FD, tells the debugger it exited
+    pub fn exited(&mut self) -> io::Result<()> {
+        self.stop = Stop::Exited(0);
+        if self.running {
+            self.running = false;
+            self.send(&self.stop_reply(None))?;
+        }
+        Ok(())
+    }
+
+    // Reports why the program stopped and serves the debugger until it
+    // resumes or leaves.
+    pub fn stopped(&mut self, cpu: &mut Cpu) -> io::Result<Resume> {
+        if self.running {
+            self.running = false;
+            let reply = self.stop_reply(Some(cpu));
+            self.send(&reply)?;
+        }
+        loop {
+            let packet = self.receive()?;
+            if let Some(resume) = self.handle(cpu, &packet)? {
+                self.resumed = true;
+                self.watch_hit = None;
+                return Ok(resume);
+            }
+        }
+    }
+
+    // Some when the packet resumes the program
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> io::Result<Option<Resume>> {
+        let reply = match packet {
+            "?" => self.stop_reply(Some(cpu)),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            "QStartNoAckMode" => {
+                self.send("OK")?;
+                self.no_ack = true;
+                return Ok(None);
+            },
+            "g" => (0..REGISTERS).map(|n| read_register(cpu, n)).collect(),
+            "D" | "D;1" => {
+                self.send("OK")?;
+                return Ok(Some(Resume::Detach));
+            },
+            "k" => return Ok(Some(Resume::Kill)),
+            "vKill;1" => {
+                self.send("OK")?;
+                return Ok(Some(Resume::Kill));
+            },
+            _ if packet.starts_with("qSupported") =>
+                "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+;vContSupported+"
+                    .to_string(),
+            _ if packet.starts_with("qXfer:features:read:target.xml:") =>
+                target_xml_chunk(&packet["qXfer:features:read:target.xml:".len()..])
+                    .unwrap_or_else(|| "E01".to_string()),
+            _ if packet.starts_with('H') || packet.starts_with('T') => "OK".to_string(),
+            _ if packet.starts_with('G') => match write_registers(cpu, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            _ if packet.starts_with('p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < REGISTERS => read_register(cpu, n),
+                _ => "E01".to_string(),
+            },
+            _ if packet.starts_with('P') => match write_register(cpu, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            _ if packet.starts_with('m') => read_memory(cpu, &packet[1..])
+                .unwrap_or_else(|| "E01".to_string()),
+            _ if packet.starts_with('M') => match write_memory(cpu, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            _ if packet.starts_with('Z') || packet.starts_with('z') =>
+                match self.set_breakpoint(packet) {
+                    Some(true) => "OK".to_string(),
+                    Some(false) => String::new(),
+                    None => "E01".to_string(),
+                },
+            _ if packet.starts_with('c') || packet.starts_with('s') => {
+                if packet.len() > 1 {
+                    match u16::from_str_radix(&packet[1..], 16) {
+                        Ok(addr) => cpu.set_pc(addr),
+                        Err(_) => return self.send("E01").map(|_| None),
+                    }
+                }
+                return Ok(Some(self.resume(packet.starts_with('s'))));
+            },
+            _ if packet.starts_with("vCont;") => {
+                // a single thread, the first action is the one for it
+                let action = packet["vCont;".len()..].split(';').next().unwrap_or("");
+                match action.chars().next() {
+                    Some('c') | Some('C') => return Ok(Some(self.resume(false))),
+                    Some('s') | Some('S') => return Ok(Some(self.resume(true))),
+                    _ => "E01".to_string(),
+                }
+            },
+            // everything else isn't supported
+            _ => String::new(),
+        };
+        self.send(&reply)?;
+        Ok(None)
+    }
+
+    fn resume(&mut self, step: bool) -> Resume {
+        self.steps = if step { Some(1) } else { None };
+        self.running = true;
+        Resume::Continue
+    }
+
+    // Z/z TYPE,ADDR,KIND, Some(false) for types we don't do
+    fn set_breakpoint(&mut self, packet: &str) -> Option<bool> {
+        let insert = packet.starts_with('Z');
+        let mut fields = packet[1..].split(',');
+        let kind = fields.next()?;
+        let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let len = u16::from_str_radix(fields.next()?.split(';').next()?, 16).ok()?;
+
+        let breakpoint = match kind {
+            "0" | "1" => Breakpoint::Pc(addr),
+            "2" | "3" | "4" => Breakpoint::Memory {
+                addr,
+                len: len.max(1),
+                watch: match kind {
+                    "2" => Watch::Write,
+                    "3" => Watch::Read,
+                    _ => Watch::Access,
+                },
+            },
+            _ => return Some(false),
+        };
+        if insert {
+            self.breakpoints.add(breakpoint);
+        } else {
+            self.breakpoints.remove_all(&breakpoint);
+        }
+        Some(true)
+    }
+
+    fn stop_reply(&self, cpu: Option<&Cpu>) -> String {
+        let signal = match self.stop {
+            Stop::Exited(code) => return format!("W{:02x}", code),
+            Stop::Signal(signal) => signal,
+            Stop::Breakpoint | Stop::Watch(..) => SIGTRAP,
+        };
+        let mut reply = format!("T{:02x}", signal);
+        if let Some(cpu) = cpu {
+            reply += &format!("{:02x}:{};", 17, read_register(cpu, 17));
+        }
+        match self.stop {
+            Stop::Breakpoint => reply += "swbreak:;",
+            Stop::Watch(watch, addr) => {
+                let kind = match watch {
+                    Watch::Write => "watch",
+                    Watch::Read => "rwatch",
+                    Watch::Access => "awatch",
+                };
+                reply += &format!("{}:{:x};", kind, addr);
+            },
+            _ => (),
+        }
+        reply
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet.push(b'$');
+        for &byte in data.as_bytes() {
+            // these would end or break the packet, send them escaped
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(byte);
+            }
+        }
+        let checksum = packet[1..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+
+        loop {
+            self.conn.write_all(&packet)?;
+            self.conn.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            // anything but a nak counts as an ack
+            if self.read_byte()? != b'-' {
+                return Ok(());
+            }
+        }
+    }
+
+    // next packet with a good checksum, acks and interrupts in between are
+    // dropped as the program is stopped anyway
+    fn receive(&mut self) -> io::Result<String> {
+        loop {
+            while self.read_byte()? != b'$' {}
+
+            let mut data = Vec::new();
+            let mut sum = 0u8;
+            loop {
+                let byte = self.read_byte()?;
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                data.push(byte);
+            }
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let good = std::str::from_utf8(&checksum).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                == Some(sum);
+
+            if !self.no_ack {
+                self.conn.write_all(if good { b"+" } else { b"-" })?;
+                self.conn.flush()?;
+            }
+            if good {
+                return Ok(String::from_utf8_lossy(&unescape(&data)).into_owned());
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.conn.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+impl ExecHook for GdbStub {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        let resumed = std::mem::take(&mut self.resumed);
+
+        // watchpoints report once the instruction that hit them has run
+        if let Some((watch, addr)) = self.watch_hit.take() {
+            self.stop = Stop::Watch(watch, addr);
+            return ControlFlow::Break(());
+        }
+        match self.steps {
+            Some(0) => {
+                self.stop = Stop::Signal(SIGTRAP);
+                return ControlFlow::Break(());
+            },
+            Some(n) => self.steps = Some(n - 1),
+            None => (),
+        }
+
+        for (_, breakpoint) in self.breakpoints.iter() {
+            match *breakpoint {
+                Breakpoint::Pc(addr) if !resumed && addr == cpu.pc() => {
+                    self.stop = Stop::Breakpoint;
+                    return ControlFlow::Break(());
+                },
+                Breakpoint::Memory { addr, watch, .. } if breakpoint.hit(cpu, instruction) => {
+                    // report the first watched address that was touched
+                    let start = cpu.memory_access(instruction)
+                        .map_or(addr, |access| (access.addr as u16).max(addr));
+                    self.watch_hit = Some((watch, start));
+                },
+                _ => (),
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn target_xml() -> String {
+    let mut regs = String::new();
+    for n in 0..REGISTER_COUNT {
+        regs += &format!("    <reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>\n", n);
+    }
+    format!("\
+<?xml version=\"1.0\"?>
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">
+<target version=\"1.0\">
+  <feature name=\"org.chip8.core\">
+{}    <reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>
+    <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>
+    <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>
+    <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>
+    <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>
+  </feature>
+</target>
+", regs)
+}
+
+// OFFSET,LENGTH of target.xml, "l" marks the last chunk
+fn target_xml_chunk(range: &str) -> Option<String> {
+    let (offset, len) = range.split_once(',')?;
+    let offset = usize::from_str_radix(offset, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    let xml = target_xml();
+    if offset >= xml.len() {
+        return Some("l".to_string());
+    }
+    let end = (offset + len).min(xml.len());
+    let prefix = if end == xml.len() { "l" } else { "m" };
+    Some(format!("{}{}", prefix, &xml[offset..end]))
+}
+
+fn read_register(cpu: &Cpu, n: usize) -> String {
+    match n {
+        0..=15 => format!("{:02x}", cpu.v()[n]),
+        16 => hex(&cpu.i().to_le_bytes()),
+        17 => hex(&cpu.pc().to_le_bytes()),
+        18 => format!("{:02x}", cpu.sp()),
+        19 => format!("{:02x}", cpu.delay_timer()),
+        _ => format!("{:02x}", cpu.sound_timer()),
+    }
+}
+
+// register `n` from little endian `bytes`
+fn set_register(cpu: &mut Cpu, n: usize, bytes: &[u8]) -> Option<()> {
+    let word = || Some(u16::from_le_bytes([*bytes.first()?, *bytes.get(1)?]));
+    let byte = *bytes.first()?;
+    match n {
+        0..=15 => cpu.set_v(n, byte),
+        16 => cpu.set_i(word()?),
+        17 => cpu.set_pc(word()?),
+        18 => cpu.set_sp(byte).ok()?,
+        19 => cpu.set_delay_timer(byte),
+        20 => cpu.set_sound_timer(byte),
+        _ => return None,
+    }
+    Some(())
+}
+
+fn register_size(n: usize) -> usize {
+    if n == 16 || n == 17 { 2 } else { 1 }
+}
+
+fn write_registers(cpu: &mut Cpu, data: &str) -> Option<()> {
+    let bytes = unhex(data)?;
+    let mut at = 0;
+    for n in 0..REGISTERS {
+        let size = register_size(n);
+        set_register(cpu, n, bytes.get(at..at + size)?)?;
+        at += size;
+    }
+    Some(())
+}
+
+// N=VALUE
+fn write_register(cpu: &mut Cpu, data: &str) -> Option<()> {
+    let (n, value) = data.split_once('=')?;
+    let n = usize::from_str_radix(n, 16).ok()?;
+    set_register(cpu, n, &unhex(value)?)
+}
+
+// ADDR,LENGTH, cut short at the end of memory
+fn read_memory(cpu: &Cpu, data: &str) -> Option<String> {
+    let (addr, len) = data.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    let size = cpu.memory_size();
+    if addr >= size {
+        return None;
+    }
+    Some(hex(&cpu.memory()[addr..addr.saturating_add(len).min(size)]))
+}
+
+// ADDR,LENGTH:BYTES
+fn write_memory(cpu: &mut Cpu, data: &str) -> Option<()> {
+    let (range, bytes) = data.split_once(':')?;
+    let (addr, len) = range.split_once(',')?;
+    let addr = usize::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    let bytes = unhex(bytes)?;
+    if bytes.len() != len || addr.saturating_add(len) > cpu.memory_size() {
+        return None;
+    }
+    cpu.memory_mut()[addr..addr + len].copy_from_slice(&bytes);
+    Some(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes().chunks(2)
+        .map(|pair| match pair.len() {
+            2 => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+// '}' escapes the byte after it
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => if let Some(&next) = bytes.next() {
+                out.push(next ^ 0x20);
+            },
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+    use crate::chip8_asm;
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    // a stub with a debugger connected to it over localhost
+    fn connect() -> (GdbStub, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let stub = GdbStub {
+            conn: Connection::Tcp(stream),
+            no_ack: false,
+            breakpoints: Breakpoints::new(),
+            steps: None,
+            running: false,
+            stop: Stop::Signal(SIGTRAP),
+            watch_hit: None,
+            resumed: false,
+        };
+        (stub, client)
+    }
+
+    fn frame(data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        format!("${}#{:02x}", data, checksum)
+    }
+
+    // What the stub sends while the debugger sends `input`, which has to
+    // end by resuming the program.
+    fn serve(cpu: &mut Cpu, input: &str) -> (GdbStub, String) {
+        let (mut stub, mut client) = connect();
+        client.write_all(input.as_bytes()).unwrap();
+        assert_eq!(stub.stopped(cpu).unwrap(), Resume::Continue);
+        if let Connection::Tcp(stream) = &stub.conn {
+            stream.shutdown(Shutdown::Write).unwrap();
+        }
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        (stub, output)
+    }
+
+    // replies to `packets`, each acked both ways
+    fn exchange(cpu: &mut Cpu, packets: &[&str]) -> (GdbStub, Vec<String>) {
+        let mut input: String = packets.iter().map(|p| frame(p) + "+").collect();
+        input += &frame("c");
+        let (stub, output) = serve(cpu, &input);
+
+        let mut replies = Vec::new();
+        let mut rest = output.as_str();
+        while let Some(start) = rest.find('$') {
+            let end = start + rest[start..].find('#').unwrap();
+            replies.push(rest[start + 1..end].to_string());
+            rest = &rest[end + 3..];
+        }
+        assert_eq!(output.matches('+').count(), packets.len() + 1);
+        (stub, replies)
+    }
+
+    fn program(quirks: Quirks) -> Cpu {
+        Cpu::new(chip8_asm!["CLS", "CLS", "CLS", "JP 0x200"], quirks).unwrap()
+    }
+
+    #[test]
+    fn packets_are_framed_and_checked() {
+        let stop = frame("T0511:0002;");
+        let mut cpu = program(Quirks::MODERN);
+
+        let (_, output) = serve(&mut cpu, &(frame("?") + "+" + &frame("c")));
+        assert_eq!(output, format!("+{}+", stop));
+
+        // a bad checksum is nakked, a nak gets the reply sent again
+        let (_, output) = serve(&mut cpu, &("$?#00".to_string() + &frame("?") + "-+" + &frame("c")));
+        assert_eq!(output, format!("-+{}{}+", stop, stop));
+    }
+
+    #[test]
+    fn special_characters_are_escaped() {
+        let (mut stub, mut client) = connect();
+        client.write_all(b"+$a}]b}\x03#1d").unwrap();
+        stub.send("}$#*").unwrap();
+        assert_eq!(stub.receive().unwrap(), "a}b#");
+
+        let mut output = [0; 13];
+        client.read_exact(&mut output).unwrap();
+        assert_eq!(&output, b"$}]}\x04}\x03}\x0a#62+");
+    }
+
+    #[test]
+    fn no_ack_mode() {
+        let mut cpu = program(Quirks::MODERN);
+        let input = frame("QStartNoAckMode") + "+" + &frame("?") + &frame("c");
+        let (stub, output) = serve(&mut cpu, &input);
+        assert!(stub.no_ack);
+        // the request itself is still acked, nothing after it
+        assert_eq!(output, format!("+{}{}", frame("OK"), frame("T0511:0002;")));
+    }
+
+    #[test]
+    fn registers() {
+        let mut cpu = program(Quirks::MODERN);
+        for x in 0..REGISTER_COUNT {
+            cpu.set_v(x, x as u8 * 0x11);
+        }
+        cpu.set_i(0x1234);
+        cpu.set_delay_timer(7);
+        cpu.set_sound_timer(9);
+        cpu.tick(Keypad::new()).unwrap();
+
+        let all = "00112233445566778899aabbccddeeff".to_string() + "3412" + "0202" + "00" + "07" + "09";
+        let mut written = "ff".repeat(REGISTER_COUNT) + "cdab" + "0003" + "02" + "01" + "00";
+        let packets = [
+            "g", "p10", "p11", "p14", "p15",
+            &format!("G{}", written),
+            "P12=0f", "P12=10", "P11=00", "P15=01",
+        ];
+        let (_, replies) = exchange(&mut cpu, &packets);
+        assert_eq!(replies, [
+            all.as_str(), "3412", "0202", "09", "E01",
+            "OK",
+            "OK", "E01", "E01", "E01",
+        ]);
+
+        written.replace_range(40..42, "0f");
+        let after: String = (0..REGISTERS).map(|n| read_register(&cpu, n)).collect();
+        assert_eq!(after, written);
+        assert_eq!(cpu.v(), &[0xff; REGISTER_COUNT]);
+        assert_eq!((cpu.i(), cpu.pc(), cpu.sp()), (0xabcd, 0x300, 15));
+        assert_eq!((cpu.delay_timer(), cpu.sound_timer()), (1, 0));
+    }
+
+    #[test]
+    fn memory_ends_with_the_platform() {
+        let mut cpu = program(Quirks::MODERN);
+        let packets = [
+            "m200,4", "mffe,4", "m1000,1", "m0,ffffffffffffffff", "mffffffffffffffff,2",
+            "Mffe,2:abcd", "Mfff,2:abcd", "M1,ffffffffffffffff:aa", "M0,2:abc",
+        ];
+        let (_, replies) = exchange(&mut cpu, &packets);
+        assert_eq!(replies[..3], ["00e000e0", "0000", "E01"]);
+        assert_eq!(replies[3].len(), 2 * 0x1000);
+        assert_eq!(replies[4..], ["E01", "OK", "E01", "E01", "E01"]);
+        assert_eq!(cpu.memory()[0xffe..0x1000], [0xab, 0xcd]);
+
+        let mut xo = program(Quirks::XO_CHIP);
+        let (_, replies) = exchange(&mut xo, &["Mfffe,2:abcd", "mfffe,8", "m10000,1"]);
+        assert_eq!(replies, ["OK", "abcd", "E01"]);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut cpu = program(Quirks::MODERN);
+        let (mut stub, replies) = exchange(&mut cpu, &["Z0,204,2", "Z1,206,2", "Z5,200,2", "Z0,zz,2"]);
+        assert_eq!(replies, ["OK", "OK", "", "E01"]);
+        let pcs: Vec<Breakpoint> = stub.breakpoints.iter().map(|(_, b)| *b).collect();
+        assert_eq!(pcs, [Breakpoint::Pc(0x204), Breakpoint::Pc(0x206)]);
+
+        let keys = Keypad::new();
+        assert!(cpu.tick_with(keys, &mut stub).unwrap().is_continue());
+        assert!(cpu.tick_with(keys, &mut stub).unwrap().is_continue());
+        assert!(cpu.tick_with(keys, &mut stub).unwrap().is_break());
+        assert_eq!((cpu.pc(), stub.stop), (0x204, Stop::Breakpoint));
+
+        assert_eq!(stub.set_breakpoint("z0,204,2"), Some(true));
+        stub.resumed = true;
+        assert!(cpu.tick_with(keys, &mut stub).unwrap().is_continue());
+        assert!(cpu.tick_with(keys, &mut stub).unwrap().is_break());
+        assert_eq!(cpu.pc(), 0x206);
+        assert_eq!(stub.breakpoints.iter().count(), 1);
+    }
+}
@@ -1,46 +1,74 @@
-use sdl2;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use std::process;
+use std::collections::HashMap;
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+
+use chip_8::frontend::{Hotkey, KeypadSource};
+use chip_8::keypad::Keypad;
+
+use crate::gamepad::Gamepads;
+use crate::keymap::KeyMap;
 
 /*
- * 1 2 3 4 
- * q w e r
- * a s d f
- * z x c v
- * 1 => 0x1,
- * 2 => 0x2,
- * 3 => 0x3,
- * 4 => 0x4,
- * q => 0x5,
- * w => 0x6,
- * e => 0x7,
- * r => 0x8,
- * a => 0x9,
- * s => 0x0,
- * d => 0xA,
- * f => 0xB,
- * z => 0xC,
- * x => 0xD,
- * c => 0xE,
- * v => 0xF,
+ * CHIP-8 keys come from a KeyMap, see keymap.rs for the layouts, and
+ * from up to two game controllers, see gamepad.rs. Every source keeps
+ * track of the inputs it holds down, a CHIP-8 key is down as long as any
+ * host key or controller bound to it is.
+ *
+ * Escape quits. F1-F9 load the save state in slot 1-9, Shift+F1-F9 save
+ * to it. Holding Backspace rewinds, F10 toggles sound, F11 stops in the
+ * debugger console.
 */
 
 pub struct Input {
     events: sdl2::EventPump,
+    keymap: KeyMap,
+    // host keys held down and the CHIP-8 key each one is bound to
+    keys_held: HashMap<Scancode, u8>,
+    gamepads: Option<Gamepads>,
+    keypad: Keypad,
+    hotkeys: Vec<Hotkey>,
+    rewind_held: bool,
 }
 
 impl Input {
-    pub fn new(ctx: &sdl2::Sdl) -> Self {
+    pub fn new(ctx: &sdl2::Sdl, keymap: KeyMap, gamepads: Option<Gamepads>) -> Self {
         Self {
             events: ctx.event_pump().unwrap(),
+            keymap,
+            keys_held: HashMap::new(),
+            gamepads,
+            keypad: Keypad::new(),
+            hotkeys: Vec::new(),
+            rewind_held: false,
         }
     }
     
-    pub fn event_poll(&mut self) -> [bool; 16] {
-        let mut keyboard_arr: [bool; 16] = [false; 16];
+    pub fn event_poll(&mut self) -> Keypad {
+        self.keypad.begin_poll();
+
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        for event in events {
+            match keypad_event(&self.keymap, &event) {
+                Some((scancode, key, true)) => {
+                    self.keys_held.insert(scancode, key);
+                    self.sync_keypad();
+                    continue;
+                },
+                Some((scancode, _, false)) => {
+                    self.keys_held.remove(&scancode);
+                    self.sync_keypad();
+                    continue;
+                },
+                None => (),
+            }
+            if let Some(gamepads) = self.gamepads.as_mut() {
+                if gamepads.handle(&event) {
+                    self.sync_keypad();
+                    continue;
+                }
+            }
 
-        for event in self.events.poll_iter() {
             match event {
                 Event::Quit { .. } |
                 Event::KeyDown { 
@@ -48,32 +76,90 @@ impl Input {
                 }
                 => {
                     println!("Exit(ESC) pressed");
-                    process::exit(0);    
+                    self.hotkeys.push(Hotkey::Quit);
                 },
                 Event::KeyDown { 
-                    keycode: Some(t), ..
+                    keycode: Some(t), keymod, repeat, ..
                 } => match t {
-                    Keycode::Num1 => keyboard_arr[0x1] = true,
-                    Keycode::Num2 => keyboard_arr[0x2] = true,
-                    Keycode::Num3 => keyboard_arr[0x3] = true,
-                    Keycode::Num4 => keyboard_arr[0x4] = true,
-                    Keycode::Q    => keyboard_arr[0x5] = true,
-                    Keycode::W    => keyboard_arr[0x6] = true,
-                    Keycode::E    => keyboard_arr[0x7] = true,
-                    Keycode::R    => keyboard_arr[0x8] = true,
-                    Keycode::A    => keyboard_arr[0x9] = true,
-                    Keycode::S    => keyboard_arr[0x0] = true,
-                    Keycode::D    => keyboard_arr[0xA] = true,
-                    Keycode::F    => keyboard_arr[0xB] = true,
-                    Keycode::Z    => keyboard_arr[0xC] = true,
-                    Keycode::X    => keyboard_arr[0xD] = true,
-                    Keycode::C    => keyboard_arr[0xE] = true,
-                    Keycode::V    => keyboard_arr[0xF] = true,
-                    _ => (),
+                    Keycode::Backspace => self.rewind_held = true,
+                    _ if repeat => (),
+                    Keycode::F10 => self.hotkeys.push(Hotkey::ToggleMute),
+                    Keycode::F11 => self.hotkeys.push(Hotkey::Debug),
+                    _ => if let Some(slot) = state_slot(t) {
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            self.hotkeys.push(Hotkey::SaveState(slot));
+                        } else {
+                            self.hotkeys.push(Hotkey::LoadState(slot));
+                        }
+                    },
+                },
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace), ..
+                } => self.rewind_held = false,
+                // key up events go to whichever window has focus now
+                Event::Window {
+                    win_event: WindowEvent::FocusLost, ..
+                } => {
+                    self.keys_held.clear();
+                    self.sync_keypad();
+                    self.rewind_held = false;
                 },
                 _ => (),
             };
         }
-        keyboard_arr
+        self.keypad
+    }
+
+    // presses and releases keypad keys to match what all sources hold
+    fn sync_keypad(&mut self) {
+        let mut held = self.gamepads.as_ref().map_or(0, |g| g.held());
+        for key in self.keys_held.values() {
+            held |= 1 << key;
+        }
+        for key in 0..16 {
+            if held & (1 << key) != 0 {
+                self.keypad.press(key);
+            } else {
+                self.keypad.release(key);
+            }
+        }
+    }
+}
+
+impl KeypadSource for Input {
+    fn poll(&mut self) -> Keypad {
+        self.event_poll()
+    }
+
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    fn rewind_held(&self) -> bool {
+        self.rewind_held
+    }
+}
+
+// host key and CHIP-8 key of a key event and whether it went down
+fn keypad_event(keymap: &KeyMap, event: &Event) -> Option<(Scancode, u8, bool)> {
+    match *event {
+        Event::KeyDown { scancode: Some(sc), .. } => Some((sc, keymap.key(sc)?, true)),
+        Event::KeyUp { scancode: Some(sc), .. } => Some((sc, keymap.key(sc)?, false)),
+        _ => None,
+    }
+}
+
+fn state_slot(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        _ => None,
     }
 }
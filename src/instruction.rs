@@ -0,0 +1,494 @@
+use std::fmt;
+
+// A decoded CHIP-8 / SUPER-CHIP / XO-CHIP instruction. Register operands
+// are indices into V, comments show the opcode each variant decodes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,                              // 00E0
+    Ret,                              // 00EE
+    ScrollDown(u8),                   // 00CN
+    ScrollUp(u8),                     // 00DN
+    ScrollRight,                      // 00FB
+    ScrollLeft,                       // 00FC
+    Exit,                             // 00FD
+    LoRes,                            // 00FE
+    HiRes,                            // 00FF
+    Sys(u16),                         // 0NNN
+    Jump(u16),                        // 1NNN
+    Call(u16),                        // 2NNN
+    SkipEqByte(usize, u8),            // 3XKK
+    SkipNeByte(usize, u8),            // 4XKK
+    SkipEqReg(usize, usize),          // 5XY0
+    SaveRange(usize, usize),          // 5XY2
+    LoadRange(usize, usize),          // 5XY3
+    LoadByte(usize, u8),              // 6XKK
+    AddByte(usize, u8),               // 7XKK
+    Move(usize, usize),               // 8XY0
+    Or(usize, usize),                 // 8XY1
+    And(usize, usize),                // 8XY2
+    Xor(usize, usize),                // 8XY3
+    Add(usize, usize),                // 8XY4
+    Sub(usize, usize),                // 8XY5
+    Shr(usize, usize),                // 8XY6
+    SubN(usize, usize),               // 8XY7
+    Shl(usize, usize),                // 8XYE
+    SkipNeReg(usize, usize),          // 9XY0
+    LoadI(u16),                       // ANNN
+    JumpOffset(usize, u16),           // BNNN, X matters for the BXNN quirk
+    Random(usize, u8),                // CXKK
+    Draw(usize, usize, u8),           // DXYN, N == 0 draws a 16x16 sprite
+    SkipKey(usize),                   // EX9E
+    SkipNotKey(usize),                // EXA1
+    LoadILong(u16),                   // F000 NNNN
+    Plane(u8),                        // FN01
+    Audio,                            // F002
+    LoadDelay(usize),                 // FX07
+    WaitKey(usize),                   // FX0A
+    SetDelay(usize),                  // FX15
+    SetSound(usize),                  // FX18
+    AddI(usize),                      // FX1E
+    Font(usize),                      // FX29
+    BigFont(usize),                   // FX30
+    Bcd(usize),                       // FX33
+    Pitch(usize),                     // FX3A
+    Store(usize),                     // FX55
+    Load(usize),                      // FX65
+    SaveFlags(usize),                 // FX75
+    LoadFlags(usize),                 // FX85
+}
+
+// Rough grouping of the instructions by what they do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OpClass {
+    // jumps, calls, returns and the like
+    Flow,
+    // conditional skips on register values
+    Skip,
+    // arithmetic and logic on V registers
+    Alu,
+    // I and memory
+    Memory,
+    // anything that changes the screen or how it is drawn
+    Draw,
+    Key,
+    Timer,
+    Sound,
+}
+
+impl OpClass {
+    pub const ALL: [OpClass; 8] = [
+        OpClass::Flow, OpClass::Skip, OpClass::Alu, OpClass::Memory,
+        OpClass::Draw, OpClass::Key, OpClass::Timer, OpClass::Sound,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpClass::Flow => "flow",
+            OpClass::Skip => "skip",
+            OpClass::Alu => "alu",
+            OpClass::Memory => "mem",
+            OpClass::Draw => "draw",
+            OpClass::Key => "key",
+            OpClass::Timer => "timer",
+            OpClass::Sound => "sound",
+        }
+    }
+}
+
+impl std::str::FromStr for OpClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        match OpClass::ALL.iter().find(|class| class.name() == s) {
+            Some(class) => Ok(*class),
+            None => Err(format!("unknown opcode class: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    // opcode is not part of any supported instruction set
+    Unknown(u16),
+    // F000 has to be followed by a second word holding the address
+    MissingOperand(u16),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Unknown(op) => write!(f, "unknown opcode 0x{:04x}", op),
+            DecodeError::MissingOperand(op) =>
+                write!(f, "opcode 0x{:04x} is missing its operand word", op),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Instruction {
+    // Decodes a single opcode. F000 needs the word after it, use
+    // `decode_long` or `decode_at` for that one.
+    pub fn decode(op: u16) -> Result<Instruction, DecodeError> {
+        let x = ((op << 4) >> 12) as usize;
+        let y = ((op << 8) >> 12) as usize;
+        let kk = ((op << 8) >> 8) as u8;
+        let n = ((op << 12) >> 12) as u8;
+        let nnn = (op << 4) >> 4;
+
+        let instruction = match op >> 12 {
+            0x0 => match op {
+                0x00e0 => Instruction::Cls,
+                0x00ee => Instruction::Ret,
+                0x00fb => Instruction::ScrollRight,
+                0x00fc => Instruction::ScrollLeft,
+                0x00fd => Instruction::Exit,
+                0x00fe => Instruction::LoRes,
+                0x00ff => Instruction::HiRes,
+                _ if op & 0xfff0 == 0x00c0 => Instruction::ScrollDown(n),
+                _ if op & 0xfff0 == 0x00d0 => Instruction::ScrollUp(n),
+                _ => Instruction::Sys(nnn),
+            },
+            0x1 => Instruction::Jump(nnn),
+            0x2 => Instruction::Call(nnn),
+            0x3 => Instruction::SkipEqByte(x, kk),
+            0x4 => Instruction::SkipNeByte(x, kk),
+            0x5 => match n {
+                0x0 => Instruction::SkipEqReg(x, y),
+                0x2 => Instruction::SaveRange(x, y),
+                0x3 => Instruction::LoadRange(x, y),
+                _ => return Err(DecodeError::Unknown(op)),
+            },
+            0x6 => Instruction::LoadByte(x, kk),
+            0x7 => Instruction::AddByte(x, kk),
+            0x8 => match n {
+                0x0 => Instruction::Move(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::Add(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::Shr(x, y),
+                0x7 => Instruction::SubN(x, y),
+                0xE => Instruction::Shl(x, y),
+                _ => return Err(DecodeError::Unknown(op)),
+            },
+            0x9 if n == 0 => Instruction::SkipNeReg(x, y),
+            0xA => Instruction::LoadI(nnn),
+            0xB => Instruction::JumpOffset(x, nnn),
+            0xC => Instruction::Random(x, kk),
+            0xD => Instruction::Draw(x, y, n),
+            0xE => match kk {
+                0x9E => Instruction::SkipKey(x),
+                0xA1 => Instruction::SkipNotKey(x),
+                _ => return Err(DecodeError::Unknown(op)),
+            },
+            0xF => match kk {
+                0x00 if x == 0 => return Err(DecodeError::MissingOperand(op)),
+                0x01 => Instruction::Plane(x as u8),
+                0x02 if x == 0 => Instruction::Audio,
+                0x07 => Instruction::LoadDelay(x),
+                0x0A => Instruction::WaitKey(x),
+                0x15 => Instruction::SetDelay(x),
+                0x18 => Instruction::SetSound(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::Font(x),
+                0x30 => Instruction::BigFont(x),
+                0x33 => Instruction::Bcd(x),
+                0x3A => Instruction::Pitch(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                0x75 => Instruction::SaveFlags(x),
+                0x85 => Instruction::LoadFlags(x),
+                _ => return Err(DecodeError::Unknown(op)),
+            },
+            _ => return Err(DecodeError::Unknown(op)),
+        };
+        Ok(instruction)
+    }
+
+    // Decodes an opcode together with the word that follows it, only
+    // F000 NNNN actually uses the second word.
+    pub fn decode_long(op: u16, next: u16) -> Result<Instruction, DecodeError> {
+        if op == 0xf000 {
+            return Ok(Instruction::LoadILong(next));
+        }
+        Instruction::decode(op)
+    }
+
+    // Decodes the instruction stored in `mem` at `addr`.
+    pub fn decode_at(mem: &[u8], addr: usize) -> Result<Instruction, DecodeError> {
+        let word = |at: usize| match (mem.get(at), mem.get(at + 1)) {
+            (Some(hi), Some(lo)) => Some(u16::from_be_bytes([*hi, *lo])),
+            _ => None,
+        };
+        let op = match word(addr) {
+            Some(op) => op,
+            None => return Err(DecodeError::Unknown(0)),
+        };
+        if op == 0xf000 {
+            return match word(addr + 2) {
+                Some(next) => Instruction::decode_long(op, next),
+                None => Err(DecodeError::MissingOperand(op)),
+            };
+        }
+        Instruction::decode(op)
+    }
+
+    // First (for most instructions the only) opcode word.
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: usize, y: usize, n: u16| {
+            op | (x as u16 & 0xf) << 8 | (y as u16 & 0xf) << 4 | n
+        };
+        let xkk = |op: u16, x: usize, kk: u8| op | (x as u16 & 0xf) << 8 | kk as u16;
+
+        match *self {
+            Instruction::Cls => 0x00e0,
+            Instruction::Ret => 0x00ee,
+            Instruction::ScrollDown(n) => 0x00c0 | (n as u16 & 0xf),
+            Instruction::ScrollUp(n) => 0x00d0 | (n as u16 & 0xf),
+            Instruction::ScrollRight => 0x00fb,
+            Instruction::ScrollLeft => 0x00fc,
+            Instruction::Exit => 0x00fd,
+            Instruction::LoRes => 0x00fe,
+            Instruction::HiRes => 0x00ff,
+            Instruction::Sys(nnn) => nnn & 0xfff,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0xfff),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0xfff),
+            Instruction::SkipEqByte(x, kk) => xkk(0x3000, x, kk),
+            Instruction::SkipNeByte(x, kk) => xkk(0x4000, x, kk),
+            Instruction::SkipEqReg(x, y) => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange(x, y) => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xy(0x5000, x, y, 0x3),
+            Instruction::LoadByte(x, kk) => xkk(0x6000, x, kk),
+            Instruction::AddByte(x, kk) => xkk(0x7000, x, kk),
+            Instruction::Move(x, y) => xy(0x8000, x, y, 0x0),
+            Instruction::Or(x, y) => xy(0x8000, x, y, 0x1),
+            Instruction::And(x, y) => xy(0x8000, x, y, 0x2),
+            Instruction::Xor(x, y) => xy(0x8000, x, y, 0x3),
+            Instruction::Add(x, y) => xy(0x8000, x, y, 0x4),
+            Instruction::Sub(x, y) => xy(0x8000, x, y, 0x5),
+            Instruction::Shr(x, y) => xy(0x8000, x, y, 0x6),
+            Instruction::SubN(x, y) => xy(0x8000, x, y, 0x7),
+            Instruction::Shl(x, y) => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNeReg(x, y) => xy(0x9000, x, y, 0x0),
+            Instruction::LoadI(nnn) => 0xa000 | (nnn & 0xfff),
+            Instruction::JumpOffset(_, nnn) => 0xb000 | (nnn & 0xfff),
+            Instruction::Random(x, kk) => xkk(0xc000, x, kk),
+            Instruction::Draw(x, y, n) => xy(0xd000, x, y, n as u16 & 0xf),
+            Instruction::SkipKey(x) => xkk(0xe000, x, 0x9E),
+            Instruction::SkipNotKey(x) => xkk(0xe000, x, 0xA1),
+            Instruction::LoadILong(_) => 0xf000,
+            Instruction::Plane(n) => xkk(0xf000, n as usize, 0x01),
+            Instruction::Audio => 0xf002,
+            Instruction::LoadDelay(x) => xkk(0xf000, x, 0x07),
+            Instruction::WaitKey(x) => xkk(0xf000, x, 0x0A),
+            Instruction::SetDelay(x) => xkk(0xf000, x, 0x15),
+            Instruction::SetSound(x) => xkk(0xf000, x, 0x18),
+            Instruction::AddI(x) => xkk(0xf000, x, 0x1E),
+            Instruction::Font(x) => xkk(0xf000, x, 0x29),
+            Instruction::BigFont(x) => xkk(0xf000, x, 0x30),
+            Instruction::Bcd(x) => xkk(0xf000, x, 0x33),
+            Instruction::Pitch(x) => xkk(0xf000, x, 0x3A),
+            Instruction::Store(x) => xkk(0xf000, x, 0x55),
+            Instruction::Load(x) => xkk(0xf000, x, 0x65),
+            Instruction::SaveFlags(x) => xkk(0xf000, x, 0x75),
+            Instruction::LoadFlags(x) => xkk(0xf000, x, 0x85),
+        }
+    }
+
+    // Full encoding, four bytes for F000 NNNN and two for everything else.
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LoadILong(nnnn) = self {
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+        }
+        bytes
+    }
+
+    // Opcode family as in the comments on Instruction, "8XY4" for Add.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::ScrollDown(_) => "00CN",
+            Instruction::ScrollUp(_) => "00DN",
+            Instruction::ScrollRight => "00FB",
+            Instruction::ScrollLeft => "00FC",
+            Instruction::Exit => "00FD",
+            Instruction::LoRes => "00FE",
+            Instruction::HiRes => "00FF",
+            Instruction::Sys(_) => "0NNN",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipEqByte(..) => "3XKK",
+            Instruction::SkipNeByte(..) => "4XKK",
+            Instruction::SkipEqReg(..) => "5XY0",
+            Instruction::SaveRange(..) => "5XY2",
+            Instruction::LoadRange(..) => "5XY3",
+            Instruction::LoadByte(..) => "6XKK",
+            Instruction::AddByte(..) => "7XKK",
+            Instruction::Move(..) => "8XY0",
+            Instruction::Or(..) => "8XY1",
+            Instruction::And(..) => "8XY2",
+            Instruction::Xor(..) => "8XY3",
+            Instruction::Add(..) => "8XY4",
+            Instruction::Sub(..) => "8XY5",
+            Instruction::Shr(..) => "8XY6",
+            Instruction::SubN(..) => "8XY7",
+            Instruction::Shl(..) => "8XYE",
+            Instruction::SkipNeReg(..) => "9XY0",
+            Instruction::LoadI(_) => "ANNN",
+            Instruction::JumpOffset(..) => "BNNN",
+            Instruction::Random(..) => "CXKK",
+            Instruction::Draw(..) => "DXYN",
+            Instruction::SkipKey(_) => "EX9E",
+            Instruction::SkipNotKey(_) => "EXA1",
+            Instruction::LoadILong(_) => "F000 NNNN",
+            Instruction::Plane(_) => "FN01",
+            Instruction::Audio => "F002",
+            Instruction::LoadDelay(_) => "FX07",
+            Instruction::WaitKey(_) => "FX0A",
+            Instruction::SetDelay(_) => "FX15",
+            Instruction::SetSound(_) => "FX18",
+            Instruction::AddI(_) => "FX1E",
+            Instruction::Font(_) => "FX29",
+            Instruction::BigFont(_) => "FX30",
+            Instruction::Bcd(_) => "FX33",
+            Instruction::Pitch(_) => "FX3A",
+            Instruction::Store(_) => "FX55",
+            Instruction::Load(_) => "FX65",
+            Instruction::SaveFlags(_) => "FX75",
+            Instruction::LoadFlags(_) => "FX85",
+        }
+    }
+
+    pub fn class(&self) -> OpClass {
+        match self {
+            Instruction::Sys(_) | Instruction::Jump(_) | Instruction::Call(_) |
+            Instruction::Ret | Instruction::JumpOffset(..) | Instruction::Exit => OpClass::Flow,
+            Instruction::SkipEqByte(..) | Instruction::SkipNeByte(..) |
+            Instruction::SkipEqReg(..) | Instruction::SkipNeReg(..) => OpClass::Skip,
+            Instruction::LoadByte(..) | Instruction::AddByte(..) | Instruction::Move(..) |
+            Instruction::Or(..) | Instruction::And(..) | Instruction::Xor(..) |
+            Instruction::Add(..) | Instruction::Sub(..) | Instruction::Shr(..) |
+            Instruction::SubN(..) | Instruction::Shl(..) | Instruction::Random(..) => OpClass::Alu,
+            Instruction::LoadI(_) | Instruction::LoadILong(_) | Instruction::AddI(_) |
+            Instruction::Font(_) | Instruction::BigFont(_) | Instruction::Bcd(_) |
+            Instruction::Store(_) | Instruction::Load(_) | Instruction::SaveRange(..) |
+            Instruction::LoadRange(..) | Instruction::SaveFlags(_) |
+            Instruction::LoadFlags(_) => OpClass::Memory,
+            Instruction::Cls | Instruction::Draw(..) | Instruction::ScrollDown(_) |
+            Instruction::ScrollUp(_) | Instruction::ScrollRight | Instruction::ScrollLeft |
+            Instruction::LoRes | Instruction::HiRes | Instruction::Plane(_) => OpClass::Draw,
+            Instruction::SkipKey(_) | Instruction::SkipNotKey(_) |
+            Instruction::WaitKey(_) => OpClass::Key,
+            Instruction::LoadDelay(_) | Instruction::SetDelay(_) => OpClass::Timer,
+            Instruction::SetSound(_) | Instruction::Audio | Instruction::Pitch(_) => OpClass::Sound,
+        }
+    }
+
+    // Size in bytes.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    // true for the instructions that skip the next one or not
+    pub fn is_skip(&self) -> bool {
+        matches!(self,
+            Instruction::SkipEqByte(..) | Instruction::SkipNeByte(..) |
+            Instruction::SkipEqReg(..) | Instruction::SkipNeReg(..) |
+            Instruction::SkipKey(..) | Instruction::SkipNotKey(..))
+    }
+}
+
+// Mnemonics as in Cowgod's Chip-8 technical reference, extended with the
+// usual SUPER-CHIP and XO-CHIP ones.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LoRes => write!(f, "LOW"),
+            Instruction::HiRes => write!(f, "HIGH"),
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Jump(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipEqByte(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SkipNeByte(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SkipEqReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Instruction::Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpOffset(_, nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Random(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadILong(nnnn) => write!(f, "LD I, LONG 0x{:04X}", nnnn),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch(x) => write!(f, "PITCH V{:X}", x),
+            Instruction::Store(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::SaveFlags(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for op in 0..=0xffff {
+            if let Ok(instruction) = Instruction::decode(op) {
+                assert_eq!(instruction.encode(), op, "{}", instruction);
+                assert_eq!(instruction.size(), 2);
+            }
+        }
+        let long = Instruction::decode_long(0xf000, 0xabcd).unwrap();
+        assert_eq!(long, Instruction::LoadILong(0xabcd));
+        assert_eq!(long.to_bytes(), [0xf0, 0x00, 0xab, 0xcd]);
+        assert_eq!(Instruction::decode(0xf000), Err(DecodeError::MissingOperand(0xf000)));
+    }
+
+    #[test]
+    fn decode_at_needs_the_whole_instruction() {
+        assert_eq!(Instruction::decode_at(&[0x00, 0xe0], 0), Ok(Instruction::Cls));
+        assert_eq!(Instruction::decode_at(&[0xf0, 0x00, 0x12], 0),
+            Err(DecodeError::MissingOperand(0xf000)));
+        assert!(Instruction::decode_at(&[0x00], 0).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use sdl2::keyboard::Scancode;
+
+use crate::config::KeysConfig;
+
+/*
+ * Keyboard layouts, by physical key position so they come out the same on
+ * AZERTY or Dvorak keyboards.
+ *
+ * linear    vip       numpad
+ * 1 2 3 4   1 2 3 C   7 8 9 -> 7 8 9, / * - + -> A B C D
+ * 5 6 7 8   4 5 6 D   4 5 6 -> 4 5 6, Enter   -> E
+ * 9 0 A B   7 8 9 E   1 2 3 -> 1 2 3, .       -> F
+ * C D E F   A 0 B F   0     -> 0
+ *
+ * linear and vip sit on the 1234/QWER/ASDF/ZXCV block.
+ */
+const BLOCK: [Scancode; 16] = [
+    Scancode::Num1, Scancode::Num2, Scancode::Num3, Scancode::Num4,
+    Scancode::Q, Scancode::W, Scancode::E, Scancode::R,
+    Scancode::A, Scancode::S, Scancode::D, Scancode::F,
+    Scancode::Z, Scancode::X, Scancode::C, Scancode::V,
+];
+
+const LINEAR_KEYS: [u8; 16] = [
+    0x1, 0x2, 0x3, 0x4,
+    0x5, 0x6, 0x7, 0x8,
+    0x9, 0x0, 0xA, 0xB,
+    0xC, 0xD, 0xE, 0xF,
+];
+
+const VIP_KEYS: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+const NUMPAD: [(Scancode, u8); 16] = [
+    (Scancode::Kp0, 0x0), (Scancode::Kp1, 0x1), (Scancode::Kp2, 0x2),
+    (Scancode::Kp3, 0x3), (Scancode::Kp4, 0x4), (Scancode::Kp5, 0x5),
+    (Scancode::Kp6, 0x6), (Scancode::Kp7, 0x7), (Scancode::Kp8, 0x8),
+    (Scancode::Kp9, 0x9), (Scancode::KpDivide, 0xA), (Scancode::KpMultiply, 0xB),
+    (Scancode::KpMinus, 0xC), (Scancode::KpPlus, 0xD), (Scancode::KpEnter, 0xE),
+    (Scancode::KpPeriod, 0xF),
+];
+
+pub const PRESET_NAMES: [&str; 3] = ["linear", "vip", "numpad"];
+
+// Host keys to CHIP-8 keys, any number of host keys can share a CHIP-8 key.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    keys: HashMap<Scancode, u8>,
+}
+
+impl KeyMap {
+    pub fn preset(name: &str) -> Option<KeyMap> {
+        let pairs: Vec<(Scancode, u8)> = match name.to_ascii_lowercase().as_str() {
+            "linear" | "qwerty" => BLOCK.into_iter().zip(LINEAR_KEYS).collect(),
+            "vip" | "cosmac-vip" => BLOCK.into_iter().zip(VIP_KEYS).collect(),
+            "numpad" => NUMPAD.to_vec(),
+            _ => return None,
+        };
+        Some(KeyMap { keys: pairs.into_iter().collect() })
+    }
+
+    pub fn from_config(cfg: &KeysConfig) -> Result<KeyMap, String> {
+        let preset = cfg.preset.as_deref().unwrap_or("linear");
+        let mut map = match KeyMap::preset(preset) {
+            Some(map) => map,
+            None => return Err(format!("unknown key preset {}, expected one of {}",
+                preset, PRESET_NAMES.join(", "))),
+        };
+
+        for (key, names) in cfg.bind.iter() {
+            let key = match parse_key(key) {
+                Some(key) => key,
+                None => return Err(format!("{} is not a CHIP-8 key (0-F)", key)),
+            };
+            map.keys.retain(|_, k| *k != key);
+            for name in names {
+                match Scancode::from_name(name) {
+                    Some(scancode) => { map.keys.insert(scancode, key); },
+                    None => return Err(format!("unknown key name {}", name)),
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn key(&self, scancode: Scancode) -> Option<u8> {
+        self.keys.get(&scancode).copied()
+    }
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::preset("linear").unwrap()
+    }
+}
+
+// "A", "a" or "0xA"
+pub fn parse_key(key: &str) -> Option<u8> {
+    let digits = key.strip_prefix("0x").unwrap_or(key);
+    match u8::from_str_radix(digits, 16) {
+        Ok(key) if key < 16 => Some(key),
+        _ => None,
+    }
+}
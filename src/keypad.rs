@@ -0,0 +1,84 @@
+// State of the 16 key hex keypad. Frontends call `begin_poll` before
+// feeding it the press and release events of one poll, so besides which
+// keys are held it also knows which ones went down or up in that poll.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Keypad {
+    down: u16,
+    pressed: u16,
+    released: u16,
+    // bumped by every poll, lets the cpu tell a new poll from a repeated one
+    generation: u32,
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // keypad with `keys` held and no press or release events
+    pub fn from_state(keys: [bool; 16]) -> Self {
+        let mut keypad = Self::new();
+        for (key, down) in keys.iter().enumerate() {
+            if *down {
+                keypad.down |= 1 << key;
+            }
+        }
+        keypad
+    }
+
+    // forget the events of the previous poll
+    pub fn begin_poll(&mut self) {
+        self.pressed = 0;
+        self.released = 0;
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub fn press(&mut self, key: u8) {
+        let bit = 1 << (key & 0xf);
+        if self.down & bit == 0 {
+            self.pressed |= bit;
+        }
+        self.down |= bit;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        let bit = 1 << (key & 0xf);
+        if self.down & bit != 0 {
+            self.released |= bit;
+        }
+        self.down &= !bit;
+    }
+
+    pub fn release_all(&mut self) {
+        for key in 0..16 {
+            self.release(key);
+        }
+    }
+
+    // key is held down
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.down & (1 << (key & 0xf)) != 0
+    }
+
+    // key went down during the last poll, it may be up again already
+    pub fn just_pressed(&self, key: u8) -> bool {
+        self.pressed & (1 << (key & 0xf)) != 0
+    }
+
+    // key went up during the last poll
+    pub fn just_released(&self, key: u8) -> bool {
+        self.released & (1 << (key & 0xf)) != 0
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    pub fn state(&self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (key, down) in keys.iter_mut().enumerate() {
+            *down = self.is_pressed(key as u8);
+        }
+        keys
+    }
+}
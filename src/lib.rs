@@ -0,0 +1,24 @@
+pub mod asm;
+pub mod consts;
+pub mod coverage;
+pub mod cpu;
+pub mod debug;
+pub mod disasm;
+pub mod error;
+pub mod frontend;
+pub mod gdb;
+pub mod instruction;
+pub mod keypad;
+pub mod profile;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod sanitize;
+pub mod savestate;
+pub mod scheduler;
+pub mod trace;
+
+pub use consts::{BIG_FONTS, FONTS};
+pub use cpu::Cpu;
+pub use error::{Chip8Error, Fault};
+pub use keypad::Keypad;
@@ -1,92 +1,350 @@
-mod cpu;
 mod video;
 mod input;
-mod consts;
+mod audio;
+mod args;
+mod cli;
+mod config;
+mod debugger;
+mod gamepad;
+mod keymap;
+mod romdb;
+mod tools;
 
 use std::fs;
 use std::env;
-use std::io;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::process;
+
+use chip_8::cpu::Cpu;
+use chip_8::error::Chip8Error;
+use chip_8::consts::{DEFAULT_INSTRUCTIONS_PER_FRAME, HIRES_SCR_HEIGHT, HIRES_SCR_WIDTH, START_ADDR};
+use chip_8::scheduler::{Clock, ManualClock, Scheduler, SystemClock};
+use chip_8::savestate::Snapshot;
+use chip_8::rewind::Rewind;
+use chip_8::rng;
+use chip_8::frontend::{AudioSink, DisplaySink, Hotkey, KeypadSource};
+use chip_8::frontend::{NullAudio, NullDisplay, NullKeypad};
+use chip_8::gdb;
 
-use cpu::Cpu;
 use video::Video;
 use input::Input;
-
-use sdl2;
+use audio::Beeper;
+use cli::{Command, Config, DEFAULT_BG, DEFAULT_FG};
+use config::FileConfig;
+use debugger::{Debugger, Monitor};
+use gamepad::{Gamepads, GamepadProfiles};
+use keymap::KeyMap;
+use romdb::RomInfo;
+use tools::Tools;
 
 fn main() {
-    let cfg = parse_args(env::args());
+    let mut args = env::args().peekable();
+    let prog_name = args.peek().cloned().unwrap_or_else(|| "chip_8".to_string());
+
+    let mut cfg = match cli::parse_args(args) {
+        Ok(Command::Run(cfg)) => *cfg,
+        Ok(Command::Help) => {
+            print!("{}", cli::usage(&prog_name));
+            return;
+        },
+        Ok(Command::Version) => {
+            println!("chip_8 {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Err(e) => {
+            eprintln!("{}: {}", prog_name, e);
+            eprintln!("Try '{} --help' for more information.", prog_name);
+            process::exit(2);
+        },
+    };
 
     let read_mem: Vec<u8> = match read_chip8_programm(&cfg.chip8_filepath) {
         Ok(t) => t,
-        Err(e) => panic!("{}", e),
+        Err(e) => {
+            eprintln!("{}: can't read {}: {}", prog_name, cfg.chip8_filepath, e);
+            process::exit(1);
+        },
     };
 
-    let sdl_context = sdl2::init().unwrap();
+    let rom_info = match romdb::lookup(&read_mem, cfg.rom_db.as_deref().map(Path::new)) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Can't read the ROM database, using defaults: {}", e);
+            None
+        },
+    };
+    let mut title = "chip-8 emulator".to_string();
+    let mut colours = palette(cfg.fg.unwrap_or(DEFAULT_FG), cfg.bg.unwrap_or(DEFAULT_BG));
+    if let Some(info) = &rom_info {
+        title = rom_title(info);
+        println!("{}", title);
+        if !info.keys.is_empty() {
+            let keys: Vec<String> = info.keys.iter()
+                .map(|(name, key)| format!("{} {:X}", name, key))
+                .collect();
+            println!("Keys: {}", keys.join(", "));
+        }
 
-    let mut video = Video::new(&sdl_context, 680,320,"chip-8 emulator",
-        (0, 0, 0),
-        (255, 255, 255));
+        // command line options win over the database
+        cfg.quirks = cfg.quirks.or(info.quirks);
+        cfg.instructions_per_frame = cfg.instructions_per_frame.or(info.instructions_per_frame);
+        cfg.load_addr = cfg.load_addr.or(info.load_addr);
+        if cfg.fg.is_none() && cfg.bg.is_none() && info.colours.len() >= 4 {
+            colours.copy_from_slice(&info.colours[..4]);
+        } else if info.colours.len() >= 2 {
+            colours = palette(cfg.fg.unwrap_or(info.colours[1]),
+                cfg.bg.unwrap_or(info.colours[0]));
+        }
+    }
 
-    let mut input = Input::new(&sdl_context);
-    let mut cpu = Cpu::new(read_mem);
+    let load_addr = cfg.load_addr.unwrap_or(START_ADDR);
+    let quirks = cfg.quirks.unwrap_or_default();
+    let mut cpu = match Cpu::with_load_addr(read_mem.clone(), quirks, load_addr) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("{}: can't load {}: {}", prog_name, cfg.chip8_filepath, e);
+            process::exit(1);
+        },
+    };
+    cpu.set_rng(rng::default_rng(cfg.seed));
 
-    loop {
-        //trace_prompt(&cpu);
-        cpu.tick(input.event_poll());
-        
-        if cpu.vmem_changed {
-            match video.render_sprite_new(&cpu.vmem, 1, 1, 10) {
-                Ok(_) => (),
-                Err(e) => panic!("{}", e),
-            };
-        }
-        //clock_prev = std::time::Instant::now();
+    let mut tools = match Tools::new(&cfg, &cpu, &read_mem) {
+        Ok(tools) => tools,
+        Err(e) => {
+            eprintln!("{}: {}", prog_name, e);
+            process::exit(1);
+        },
+    };
 
-        //std::thread::sleep(std::time::Duration::new(0, 1_450_500 as u32));
-        std::thread::sleep(std::time::Duration::new(0, 1000 as u32));
+    if cfg.headless {
+        let result = run(&mut cpu, &cfg, ManualClock::new(),
+            &mut NullDisplay, &mut NullAudio, &mut NullKeypad, &mut tools);
+        finish(&cpu, &cfg, &mut tools, result);
+        return;
     }
-}
 
-fn parse_args(mut args: env::Args) -> Config {
-    let prog_name = match args.next() {
-        Some(arg) => arg,
-        None => panic!("Unreachable"),
+    let (keymap, pad_profiles) = match FileConfig::load(&cfg.chip8_filepath)
+        .and_then(|file| Ok((KeyMap::from_config(&file.keys)?,
+            GamepadProfiles::from_config(&file.gamepad)?)))
+    {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}: bad config: {}", prog_name, e);
+            process::exit(1);
+        },
+    };
+
+    let sdl_context = sdl2::init().unwrap();
+
+    // sized in hi-res pixels so both resolutions fill it exactly
+    let hires_scale = cfg.scale.div_ceil(2);
+    let mut video = Video::new(&sdl_context,
+        HIRES_SCR_WIDTH as u32 * hires_scale,
+        HIRES_SCR_HEIGHT as u32 * hires_scale,
+        &title,
+        colours,
+        cfg.fullscreen);
+
+    let gamepads = match Gamepads::new(&sdl_context, pad_profiles) {
+        Ok(gamepads) => Some(gamepads),
+        Err(e) => {
+            eprintln!("Can't open game controllers, keyboard only: {}", e);
+            None
+        },
     };
-    
-    let chip8_file = match args.next() {
-        Some(arg) => arg,
-        None => panic!("usage: {} chip-8-filename.ch8", prog_name),
+    let mut input = Input::new(&sdl_context, keymap, gamepads);
+
+    let mut beeper: Box<dyn AudioSink> = match Beeper::new(&sdl_context, &cfg.audio) {
+        Ok(beeper) => Box::new(beeper),
+        Err(e) => {
+            eprintln!("Can't open audio device, running without sound: {}", e);
+            Box::new(NullAudio)
+        },
     };
-    Config::new(chip8_file)
+
+    let result = run(&mut cpu, &cfg, SystemClock::new(),
+        &mut video, beeper.as_mut(), &mut input, &mut tools);
+    finish(&cpu, &cfg, &mut tools, result);
 }
 
-#[derive(Debug)]
-struct Config {
-    chip8_filepath: String,
+// writes the reports of the tools, a CPU fault ends the program with an
+// error after that
+fn finish(cpu: &Cpu, cfg: &Config, tools: &mut Tools, result: Result<(), Chip8Error>) {
+    tools.finish(cfg, cpu);
+    if let Err(e) = result {
+        eprintln!("CPU fault: {}", e);
+        eprintln!("{}", cpu);
+        process::exit(1);
+    }
 }
 
-impl Config {
-    fn new(filename: String) -> Self {
-        Self {
-            chip8_filepath: filename,
+// "Title by Author" for the window title
+fn rom_title(info: &RomInfo) -> String {
+    if info.authors.is_empty() {
+        info.title.clone()
+    } else {
+        format!("{} by {}", info.title, info.authors.join(", "))
+    }
+}
+
+// background, plane 1, plane 2 and both planes; the XO-CHIP colours sit
+// between foreground and background
+fn palette(fg: (u8, u8, u8), bg: (u8, u8, u8)) -> [(u8, u8, u8); 4] {
+    let mix = |weight: u32| {
+        let channel = |f: u8, b: u8| ((f as u32 * weight + b as u32 * (3 - weight)) / 3) as u8;
+        (channel(fg.0, bg.0), channel(fg.1, bg.1), channel(fg.2, bg.2))
+    };
+    [bg, fg, mix(2), mix(1)]
+}
+
+// main loop, only talks to the frontend through the backend traits
+fn run<C: Clock>(cpu: &mut Cpu,
+       cfg: &Config,
+       clock: C,
+       display: &mut dyn DisplaySink,
+       audio: &mut dyn AudioSink,
+       keypad: &mut dyn KeypadSource,
+       tools: &mut Tools) -> Result<(), Chip8Error>
+{
+    let mut scheduler = Scheduler::new(clock,
+        cfg.instructions_per_frame.unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME));
+    let mut frames = 0;
+    let mut rewind = Rewind::new(cfg.rewind_seconds);
+    let mut monitor = match &cfg.gdb {
+        Some(addr) => {
+            println!("Waiting for a debugger on {}", addr);
+            match gdb::listen(addr) {
+                Ok(stub) => Monitor::Gdb(stub),
+                Err(e) => {
+                    eprintln!("Can't listen for a debugger on {}: {}", addr, e);
+                    process::exit(1);
+                },
+            }
+        },
+        None => Monitor::Console(Debugger::new()),
+    };
+
+    if (cfg.debug || cfg.gdb.is_some()) && monitor.stopped(cpu).is_break() {
+        return Ok(());
+    }
+
+    loop {
+        let mut redraw = false;
+        for _ in 0..scheduler.wait_for_frame() {
+            let keys = keypad.poll();
+            for hotkey in keypad.take_hotkeys() {
+                match hotkey {
+                    Hotkey::Quit => return Ok(()),
+                    Hotkey::ToggleMute => audio.set_muted(!audio.muted()),
+                    Hotkey::Debug => {
+                        monitor.interrupt();
+                        if monitor.stopped(cpu).is_break() {
+                            return Ok(());
+                        }
+                    },
+                    _ => handle_hotkey(hotkey, cpu, cfg),
+                }
+            }
+            if monitor.interrupted() && monitor.stopped(cpu).is_break() {
+                return Ok(());
+            }
+
+            if keypad.rewind_held() {
+                // step back one frame per frame, stay on the oldest one
+                // once the history runs out
+                if let Some(snapshot) = rewind.pop() {
+                    cpu.restore(&snapshot);
+                }
+            } else {
+                let mut hooks = (monitor.hook(), &mut *tools);
+                match cpu.run_frame_with(keys, scheduler.instructions_per_frame, &mut hooks) {
+                    Ok(ControlFlow::Continue(())) => rewind.push(&cpu.snapshot()),
+                    Ok(ControlFlow::Break(())) => {
+                        // show what was drawn up to the breakpoint
+                        draw(cpu, display);
+                        audio.set_active(false);
+                        if monitor.stopped(cpu).is_break() {
+                            return Ok(());
+                        }
+                    },
+                    Err(e) => {
+                        if !monitor.fault(&e) {
+                            return Err(e);
+                        }
+                        eprintln!("CPU fault: {}", e);
+                        if monitor.stopped(cpu).is_break() {
+                            return Ok(());
+                        }
+                    },
+                }
+            }
+            redraw |= cpu.vmem_changed;
+            audio.set_active(cpu.sound_active());
+            audio.set_pattern(cpu.sound_pattern());
+
+            frames += 1;
+            if Some(frames) == cfg.frames {
+                return Ok(());
+            }
+        }
+
+        if cpu.halted() {
+            monitor.exited();
+            println!("Program exited");
+            return Ok(());
+        }
+
+        if redraw {
+            draw(cpu, display);
         }
     }
 }
 
-fn read_chip8_programm(filepath: &str) -> Result<Vec<u8>, std::io::Error>{
-    Ok(fs::read(filepath)?)
+fn draw(cpu: &Cpu, display: &mut dyn DisplaySink) {
+    let (cols, rows) = cpu.screen_size();
+    if let Err(e) = display.draw(&cpu.vmem, cols, rows) {
+        eprintln!("Can't draw the screen: {}", e);
+        process::exit(1);
+    }
 }
 
-#[allow(dead_code)]
-fn trace_prompt(cpu: &Cpu) {
-    println!("{}", cpu);
-    let mut buffer = String::new();
+// save state slots live next to the ROM as <rom>.state<slot>
+fn state_path(cfg: &Config, slot: u8) -> String {
+    format!("{}.state{}", cfg.chip8_filepath, slot)
+}
 
-    io::stdin().read_line(&mut buffer).unwrap();
+fn handle_hotkey(hotkey: Hotkey, cpu: &mut Cpu, cfg: &Config) {
+    match hotkey {
+        Hotkey::SaveState(slot) => {
+            let path = state_path(cfg, slot);
+            match fs::write(&path, cpu.snapshot().to_bytes()) {
+                Ok(_) => println!("Saved state to {}", path),
+                Err(e) => eprintln!("Can't save state to {}: {}", path, e),
+            }
+        },
+        Hotkey::LoadState(slot) => {
+            let path = state_path(cfg, slot);
+            let bytes = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    eprintln!("Can't read state from {}: {}", path, e);
+                    return;
+                },
+            };
+            match Snapshot::from_bytes(&bytes) {
+                Ok(snapshot) => {
+                    cpu.restore(&snapshot);
+                    println!("Loaded state from {}", path);
+                },
+                Err(e) => eprintln!("Can't load state from {}: {}", path, e),
+            }
+        },
+        // handled by the main loop
+        Hotkey::Quit | Hotkey::ToggleMute | Hotkey::Debug => (),
+    }
+}
 
-    match buffer.as_str().trim() {
-        "d" => println!("{}", cpu),
-        "help" => println!("Display CPU: 'd', otherwise press Enter"), 
-        _ => (),
-    };
+fn read_chip8_programm(filepath: &str) -> Result<Vec<u8>, std::io::Error>{
+    fs::read(filepath)
 }
@@ -0,0 +1,327 @@
+/*
+ * Execution profile: how often every address and every opcode family ran,
+ * which backward jumps form hot loops, and a call tree built by following
+ * 2NNN and 00EE, with the instructions run in each subroutine itself
+ * (exclusive) and in it and everything it called (inclusive).
+ *
+ * Counts are executed instructions, ticks spent waiting in FX0A are only
+ * reported as a total.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+use crate::consts::RAM_SIZE;
+use crate::cpu::{Cpu, InstructionOrd};
+use crate::debug::ExecHook;
+use crate::instruction::Instruction;
+
+// how many addresses and loops the text report lists
+const TOP_ADDRESSES: usize = 20;
+const TOP_LOOPS: usize = 10;
+
+struct Node {
+    addr: u16,
+    children: BTreeMap<u16, usize>,
+    calls: u64,
+    exclusive: u64,
+}
+
+impl Node {
+    fn new(addr: u16) -> Self {
+        Self { addr, children: BTreeMap::new(), calls: 0, exclusive: 0 }
+    }
+}
+
+pub struct Profiler {
+    per_addr: Vec<u64>,
+    families: BTreeMap<&'static str, u64>,
+    // backward jumps taken, by (target, jump address)
+    loops: HashMap<(u16, u16), u64>,
+    // call tree, the first node is where the program started
+    nodes: Vec<Node>,
+    // the call stack as indices into nodes, the root at the bottom
+    stack: Vec<usize>,
+    executed: u64,
+    pc: u16,
+}
+
+// a backward jump and what ran between its target and itself
+struct Loop {
+    start: u16,
+    end: u16,
+    iterations: u64,
+    instructions: u64,
+}
+
+impl Profiler {
+    // `entry` is where the program starts
+    pub fn new(entry: u16) -> Self {
+        let mut root = Node::new(entry);
+        root.calls = 1;
+        Self {
+            per_addr: vec![0; RAM_SIZE],
+            families: BTreeMap::new(),
+            loops: HashMap::new(),
+            nodes: vec![root],
+            stack: vec![0],
+            executed: 0,
+            pc: entry,
+        }
+    }
+
+    // (address, count) of every address that ran, most often run first
+    fn hot_spots(&self) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = self.per_addr.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(addr, count)| (addr as u16, *count))
+            .collect();
+        spots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        spots
+    }
+
+    fn families(&self) -> Vec<(&'static str, u64)> {
+        let mut families: Vec<(&'static str, u64)> = self.families.iter()
+            .map(|(family, count)| (*family, *count))
+            .collect();
+        families.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        families
+    }
+
+    fn hot_loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self.loops.iter()
+            .map(|(&(start, end), &iterations)| Loop {
+                start,
+                end,
+                iterations,
+                instructions: self.per_addr[start as usize..=end as usize].iter().sum(),
+            })
+            .collect();
+        loops.sort_by(|a, b| b.instructions.cmp(&a.instructions).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    fn inclusive(&self, node: usize) -> u64 {
+        let node = &self.nodes[node];
+        node.exclusive + node.children.values().map(|&child| self.inclusive(child)).sum::<u64>()
+    }
+
+    // Human readable report. `cpu` is the machine the profile was taken
+    // on, for the instructions at the hot addresses.
+    pub fn text(&self, cpu: &Cpu) -> String {
+        let mut out = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.executed.max(1) as f64;
+
+        let _ = writeln!(out, "{} instructions executed, {} ticks waiting for a key",
+            self.executed, (cpu.cycle() as u64).saturating_sub(self.executed));
+
+        let _ = writeln!(out, "\nHot spots\n     count       %  address  instruction");
+        for (addr, count) in self.hot_spots().into_iter().take(TOP_ADDRESSES) {
+            let _ = writeln!(out, "{:10} {:6.2}%  0x{:03x}    {}",
+                count, percent(count), addr, instruction_at(cpu, addr));
+        }
+
+        let _ = writeln!(out, "\nOpcode families\n     count       %  family");
+        for (family, count) in self.families() {
+            let _ = writeln!(out, "{:10} {:6.2}%  {}", count, percent(count), family);
+        }
+
+        let _ = writeln!(out, "\nHot loops\niterations  instructions       %  range");
+        for l in self.hot_loops().into_iter().take(TOP_LOOPS) {
+            let _ = writeln!(out, "{:10}  {:12} {:6.2}%  0x{:03x}-0x{:03x}",
+                l.iterations, l.instructions, percent(l.instructions), l.start, l.end);
+        }
+
+        let _ = writeln!(out, "\nCall tree\n inclusive       %   exclusive       calls  subroutine");
+        self.text_node(&mut out, 0, 0);
+        out
+    }
+
+    fn text_node(&self, out: &mut String, node: usize, depth: usize) {
+        let inclusive = self.inclusive(node);
+        let n = &self.nodes[node];
+        let _ = writeln!(out, "{:10} {:6.2}%  {:10}  {:10}  {:indent$}{}0x{:03x}",
+            inclusive, 100.0 * inclusive as f64 / self.executed.max(1) as f64,
+            n.exclusive, n.calls, "", if node == 0 { "main " } else { "" }, n.addr,
+            indent = depth * 2);
+        for &child in n.children.values() {
+            self.text_node(out, child, depth + 1);
+        }
+    }
+
+    // the same as `text`, as JSON with every address and loop in it
+    pub fn json(&self, cpu: &Cpu) -> String {
+        let mut out = String::new();
+        let _ = write!(out, "{{\n  \"instructions\": {},\n  \"waiting\": {},\n",
+            self.executed, (cpu.cycle() as u64).saturating_sub(self.executed));
+
+        let addresses: Vec<String> = self.hot_spots().into_iter()
+            .map(|(addr, count)| format!(
+                "    {{\"addr\": {}, \"count\": {}, \"instruction\": {}}}",
+                addr, count, json_string(&instruction_at(cpu, addr))))
+            .collect();
+        let _ = write!(out, "  \"addresses\": [\n{}\n  ],\n", addresses.join(",\n"));
+
+        let families: Vec<String> = self.families().into_iter()
+            .map(|(family, count)| format!("    {{\"family\": {}, \"count\": {}}}",
+                json_string(family), count))
+            .collect();
+        let _ = write!(out, "  \"families\": [\n{}\n  ],\n", families.join(",\n"));
+
+        let loops: Vec<String> = self.hot_loops().into_iter()
+            .map(|l| format!(
+                "    {{\"start\": {}, \"end\": {}, \"iterations\": {}, \"instructions\": {}}}",
+                l.start, l.end, l.iterations, l.instructions))
+            .collect();
+        let _ = write!(out, "  \"loops\": [\n{}\n  ],\n", loops.join(",\n"));
+
+        let _ = write!(out, "  \"calls\": {}\n}}\n", self.json_node(0));
+        out
+    }
+
+    fn json_node(&self, node: usize) -> String {
+        let n = &self.nodes[node];
+        let children: Vec<String> = n.children.values()
+            .map(|&child| self.json_node(child))
+            .collect();
+        format!("{{\"addr\": {}, \"calls\": {}, \"inclusive\": {}, \"exclusive\": {}, \"children\": [{}]}}",
+            n.addr, n.calls, self.inclusive(node), n.exclusive, children.join(", "))
+    }
+}
+
+impl ExecHook for Profiler {
+    fn before(&mut self, cpu: &Cpu, _instruction: &Instruction) -> ControlFlow<()> {
+        self.pc = cpu.pc();
+        ControlFlow::Continue(())
+    }
+
+    fn after(&mut self, cpu: &Cpu, instruction: &Instruction, _ord: InstructionOrd) {
+        self.executed += 1;
+        self.per_addr[self.pc as usize] += 1;
+        *self.families.entry(instruction.pattern()).or_insert(0) += 1;
+
+        let current = *self.stack.last().unwrap();
+        self.nodes[current].exclusive += 1;
+
+        match instruction {
+            Instruction::Call(_) if cpu.sp() as usize >= self.stack.len() => {
+                let next = self.nodes.len();
+                let child = *self.nodes[current].children.entry(cpu.pc()).or_insert(next);
+                if child == next {
+                    self.nodes.push(Node::new(cpu.pc()));
+                }
+                self.nodes[child].calls += 1;
+                self.stack.push(child);
+            },
+            Instruction::Jump(_) | Instruction::JumpOffset(..) if cpu.pc() <= self.pc => {
+                *self.loops.entry((cpu.pc(), self.pc)).or_insert(0) += 1;
+            },
+            _ => (),
+        }
+        // returns, and anything else that took frames off the stack,
+        // like loading a save state
+        self.stack.truncate(cpu.sp() as usize + 1);
+    }
+}
+
+fn instruction_at(cpu: &Cpu, addr: u16) -> String {
+    match Instruction::decode_at(cpu.memory(), addr as usize) {
+        Ok(instruction) => instruction.to_string(),
+        Err(_) => "???".to_string(),
+    }
+}
+
+// `s` quoted, with what JSON doesn't allow in a string escaped
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_asm;
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    // main calls outer, which calls leaf, then leaf itself and loops
+    fn profile(ticks: usize) -> (Profiler, Cpu) {
+        let rom = chip8_asm![
+            "CALL outer",
+            "CALL leaf",
+            "loop: ADD V0, 1",
+            "JP loop",
+            "outer: CALL leaf",
+            "ADD V1, 1",
+            "RET",
+            "leaf: ADD V2, 1",
+            "RET",
+        ];
+        let mut cpu = Cpu::new(rom, Quirks::MODERN).unwrap();
+        let mut profiler = Profiler::new(cpu.pc());
+        for _ in 0..ticks {
+            assert!(cpu.tick_with(Keypad::new(), &mut profiler).unwrap().is_continue());
+        }
+        (profiler, cpu)
+    }
+
+    #[test]
+    fn call_tree() {
+        let (profiler, _) = profile(15);
+        let tree: Vec<(u16, u64, u64, u64)> = profiler.nodes.iter().enumerate()
+            .map(|(i, n)| (n.addr, n.calls, n.exclusive, profiler.inclusive(i)))
+            .collect();
+        assert_eq!(tree, [
+            (0x200, 1, 8, 15),
+            (0x208, 1, 3, 5),
+            (0x20e, 1, 2, 2),
+            (0x20e, 1, 2, 2),
+        ]);
+        let children = |i: usize| profiler.nodes[i].children.values().copied().collect::<Vec<_>>();
+        assert_eq!(children(0), [1, 3]);
+        assert_eq!(children(1), [2]);
+        assert_eq!(profiler.stack, [0]);
+    }
+
+    #[test]
+    fn loops() {
+        let (profiler, _) = profile(15);
+        let loops: Vec<(u16, u16, u64, u64)> = profiler.hot_loops().iter()
+            .map(|l| (l.start, l.end, l.iterations, l.instructions))
+            .collect();
+        assert_eq!(loops, [(0x204, 0x206, 3, 6)]);
+        assert_eq!(profiler.hot_spots()[..2], [(0x204, 3), (0x206, 3)]);
+    }
+
+    #[test]
+    fn json() {
+        let (profiler, cpu) = profile(15);
+        let json = profiler.json(&cpu);
+        assert!(json.starts_with("{\n  \"instructions\": 15,\n  \"waiting\": 0,\n"), "{}", json);
+        assert!(json.contains("{\"addr\": 516, \"count\": 3, \"instruction\": \"ADD V0, 0x01\"}"), "{}", json);
+        assert!(json.contains("{\"family\": \"7XKK\", \"count\": 6}"), "{}", json);
+        assert!(json.contains("{\"start\": 516, \"end\": 518, \"iterations\": 3, \"instructions\": 6}"), "{}", json);
+        assert!(json.ends_with(concat!(
+            "  \"calls\": {\"addr\": 512, \"calls\": 1, \"inclusive\": 15, \"exclusive\": 8, \"children\": [",
+            "{\"addr\": 520, \"calls\": 1, \"inclusive\": 5, \"exclusive\": 3, \"children\": [",
+            "{\"addr\": 526, \"calls\": 1, \"inclusive\": 2, \"exclusive\": 2, \"children\": []}]}, ",
+            "{\"addr\": 526, \"calls\": 1, \"inclusive\": 2, \"exclusive\": 2, \"children\": []}]}\n}\n")), "{}", json);
+
+        assert_eq!(json_string("LD V0, 0x01"), "\"LD V0, 0x01\"");
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+}
@@ -0,0 +1,120 @@
+use crate::consts::{CHIP8_RAM_SIZE, RAM_SIZE};
+
+// Machine a program was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    // COSMAC VIP and its successors with 4 KiB of memory
+    Chip8,
+    // SUPER-CHIP, still 4 KiB
+    SuperChip,
+    // 64 KiB
+    XoChip,
+}
+
+impl Platform {
+    // bytes of memory programs can use, accesses past it fault
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => CHIP8_RAM_SIZE,
+            Platform::XoChip => RAM_SIZE,
+        }
+    }
+
+    // the 8x10 font for FX30 came with SUPER-CHIP
+    pub fn big_font(self) -> bool {
+        self != Platform::Chip8
+    }
+}
+
+// Behaviour of instructions that were implemented differently by the
+// various CHIP-8 interpreters over the years.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift Vx in place instead of shifting Vy into Vx
+    pub shift_vx: bool,
+    // FX55/FX65 leave I pointing past the last register they touched
+    pub load_store_increment_i: bool,
+    // BNNN is BXNN: jump to XNN + Vx instead of NNN + V0
+    pub jump_vx: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // DXYN clips sprites at the screen edges instead of wrapping them around
+    pub clip_sprites: bool,
+    // FX1E sets VF to 1 when I goes past 0xFFF, 0 otherwise
+    pub fx1e_overflow_vf: bool,
+    // how much memory there is and how the buzzer sounds
+    pub platform: Platform,
+}
+
+impl Quirks {
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_vx: false,
+        load_store_increment_i: true,
+        jump_vx: false,
+        vf_reset: true,
+        clip_sprites: true,
+        fx1e_overflow_vf: false,
+        platform: Platform::Chip8,
+    };
+
+    pub const CHIP_48: Quirks = Quirks {
+        shift_vx: true,
+        load_store_increment_i: true,
+        jump_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        fx1e_overflow_vf: false,
+        platform: Platform::Chip8,
+    };
+
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_vx: true,
+        load_store_increment_i: false,
+        jump_vx: true,
+        vf_reset: false,
+        clip_sprites: true,
+        fx1e_overflow_vf: false,
+        platform: Platform::SuperChip,
+    };
+
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_vx: false,
+        load_store_increment_i: true,
+        jump_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        fx1e_overflow_vf: false,
+        platform: Platform::XoChip,
+    };
+
+    // what most modern interpreters (and this one, historically) do
+    pub const MODERN: Quirks = Quirks {
+        shift_vx: true,
+        load_store_increment_i: false,
+        jump_vx: false,
+        vf_reset: false,
+        clip_sprites: false,
+        fx1e_overflow_vf: false,
+        platform: Platform::SuperChip,
+    };
+
+    pub const PRESET_NAMES: [&'static str; 5] =
+        ["vip", "chip48", "schip", "xochip", "modern"];
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "cosmac" => Some(Quirks::COSMAC_VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::SUPER_CHIP),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::MODERN
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+
+use crate::consts::FRAME_RATE;
+use crate::savestate::Snapshot;
+
+// Ring buffer of compressed per-frame snapshots, oldest ones are dropped
+// once `seconds` worth of frames are stored.
+pub struct Rewind {
+    frames: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+    pub fn new(seconds: u32) -> Self {
+        // grows as frames come in, a long history is mostly never filled
+        let capacity = seconds.saturating_mul(FRAME_RATE) as usize;
+        Self {
+            frames: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, snapshot: &Snapshot) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(pack(&snapshot.to_bytes()));
+    }
+
+    // most recent snapshot, None once the history is used up
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        let packed = self.frames.pop_back()?;
+        Snapshot::from_bytes(&unpack(&packed)).ok()
+    }
+}
+
+// PackBits run-length encoding. A control byte n < 128 is followed by n+1
+// literal bytes, n > 128 means the next byte repeats 257-n times.
+fn pack(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < 128 && data[i + run] == data[i] {
+            run += 1;
+        }
+        if run > 1 {
+            out.push((257 - run) as u8);
+            out.push(data[i]);
+            i += run;
+            continue;
+        }
+
+        // literal block until the next run of at least two bytes
+        let start = i;
+        while i < data.len() && i - start < 128 {
+            if i + 1 < data.len() && data[i] == data[i + 1] {
+                break;
+            }
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&data[start..i]);
+    }
+    out
+}
+
+fn unpack(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let n = data[i] as usize;
+        i += 1;
+        if n < 128 {
+            let end = (i + n + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        } else if n > 128 {
+            if let Some(byte) = data.get(i) {
+                out.extend(std::iter::repeat_n(*byte, 257 - n));
+            }
+            i += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn pack_unpack_round_trip() {
+        let mut noise = Vec::new();
+        let mut x: u32 = 1;
+        for _ in 0..1000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            noise.push((x >> 16) as u8);
+        }
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![7],
+            vec![1, 2],
+            vec![3, 3],
+            vec![0; 128],
+            vec![0; 129],
+            vec![0; 1000],
+            (0..=255).collect(),
+            [vec![1, 2, 3], vec![9; 300], vec![4, 4, 5, 6, 6, 6]].concat(),
+            noise,
+        ];
+        for data in inputs {
+            assert_eq!(unpack(&pack(&data)), data, "{} bytes", data.len());
+        }
+    }
+
+    #[test]
+    fn pack_uses_runs_and_literals() {
+        assert_eq!(pack(&[5; 4]), [253, 5]);
+        assert_eq!(pack(&[1, 2, 3]), [2, 1, 2, 3]);
+        assert_eq!(pack(&[1, 2, 2]), [0, 1, 255, 2]);
+        assert_eq!(pack(&[0; 130]), [129, 0, 255, 0]);
+        // a save state is mostly zeroed memory
+        let cpu = Cpu::new(vec![0x12, 0x00], Quirks::MODERN).unwrap();
+        assert!(pack(&cpu.snapshot().to_bytes()).len() < 2000);
+    }
+
+    #[test]
+    fn keeps_the_last_frames() {
+        let mut cpu = Cpu::new(vec![0x70, 0x01, 0x12, 0x00], Quirks::MODERN).unwrap();
+        let mut rewind = Rewind::new(1);
+        for _ in 0..FRAME_RATE + 10 {
+            rewind.push(&cpu.snapshot());
+            cpu.run_frame(Keypad::new(), 1).unwrap();
+        }
+        let mut frames = 0;
+        let mut last = None;
+        while let Some(snapshot) = rewind.pop() {
+            frames += 1;
+            last = Some(snapshot.v[0]);
+        }
+        assert_eq!(frames, FRAME_RATE);
+        // the first 10 frames were dropped, V0 went up once every other frame
+        assert_eq!(last, Some(5));
+        assert!(Rewind::new(0).pop().is_none());
+    }
+}
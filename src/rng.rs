@@ -0,0 +1,23 @@
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+// Source of the random bytes CXKK masks, swap it out for reproducible runs.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+}
+
+// every rand generator works as a source
+impl<R: RngCore> RandomSource for R {
+    fn next_byte(&mut self) -> u8 {
+        (self.next_u32() & 0xff) as u8
+    }
+}
+
+// Default generator: seeded from the OS, or from `seed` when given so the
+// same ROM and input replay produce the same numbers.
+pub fn default_rng(seed: Option<u64>) -> Box<dyn RandomSource> {
+    match seed {
+        Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+        None => Box::new(StdRng::from_entropy()),
+    }
+}
@@ -0,0 +1,276 @@
+/*
+ * Lookup of ROMs in the community CHIP-8 database
+ * (https://github.com/chip-8/chip-8-database). Its sha1-hashes.json maps
+ * the SHA-1 of a ROM to an index into programs.json, which holds the
+ * title, authors and per-ROM settings.
+ *
+ * The two files are read from the --rom-db directory if given, else from
+ * $XDG_DATA_HOME/chip_8/chip-8-database (~/.local/share if unset) if it
+ * has them. Without either no ROM is known.
+ */
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use chip_8::quirks::Quirks;
+
+// what the database knows about one ROM, None where it doesn't say
+#[derive(Debug, Clone, Default)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub quirks: Option<Quirks>,
+    pub instructions_per_frame: Option<u32>,
+    pub load_addr: Option<u16>,
+    // background, foreground and the XO-CHIP plane colours if given
+    pub colours: Vec<(u8, u8, u8)>,
+    // what the game uses its keys for, "up" -> 0x5 and so on
+    pub keys: Vec<(String, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    tickrate: Option<u32>,
+    start_address: Option<u16>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    wrap: Option<bool>,
+    jump: Option<bool>,
+    logic: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+// SHA-1 of `rom` as lowercase hex, the key the database uses
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// Ok(None) if the ROM isn't in the database or there is no database
+pub fn lookup(rom: &[u8], db_dir: Option<&Path>) -> Result<Option<RomInfo>, String> {
+    let dir = match db_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => match user_dir().filter(|d| d.join("programs.json").is_file()) {
+            Some(dir) => dir,
+            None => return Ok(None),
+        },
+    };
+
+    let hashes: HashMap<String, usize> = read_json(&dir, "sha1-hashes.json")?;
+    let hash = rom_hash(rom);
+    let index = match hashes.get(&hash) {
+        Some(index) => *index,
+        None => return Ok(None),
+    };
+
+    let mut programs: Vec<Program> = read_json(&dir, "programs.json")?;
+    if index >= programs.len() {
+        return Err(format!("programs.json: no program {} for {}", index, hash));
+    }
+    let program = programs.swap_remove(index);
+
+    let mut info = RomInfo {
+        title: program.title,
+        authors: program.authors,
+        ..RomInfo::default()
+    };
+    if let Some(rom) = program.roms.get(&hash) {
+        info.instructions_per_frame = rom.tickrate.filter(|n| *n > 0);
+        info.load_addr = rom.start_address;
+        info.quirks = rom_quirks(rom);
+        if let Some(colors) = &rom.colors {
+            info.colours = colors.pixels.iter().filter_map(|c| parse_colour(c)).collect();
+        }
+        info.keys = rom.keys.iter().map(|(name, key)| (name.clone(), *key)).collect();
+        info.keys.sort_by_key(|(_, key)| *key);
+    }
+    Ok(Some(info))
+}
+
+// quirks of the first platform we know, with the ROM's own tweaks on top
+fn rom_quirks(rom: &Rom) -> Option<Quirks> {
+    let (platform, mut quirks) = rom.platforms.iter()
+        .find_map(|p| platform_quirks(p).map(|q| (p, q)))?;
+
+    if let Some(overrides) = rom.quirky_platforms.get(platform) {
+        if let Some(shift) = overrides.shift {
+            quirks.shift_vx = shift;
+        }
+        if let Some(unchanged) = overrides.memory_leave_i_unchanged {
+            quirks.load_store_increment_i = !unchanged;
+        }
+        if let Some(wrap) = overrides.wrap {
+            quirks.clip_sprites = !wrap;
+        }
+        if let Some(jump) = overrides.jump {
+            quirks.jump_vx = jump;
+        }
+        if let Some(logic) = overrides.logic {
+            quirks.vf_reset = logic;
+        }
+    }
+    Some(quirks)
+}
+
+fn platform_quirks(platform: &str) -> Option<Quirks> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(Quirks::COSMAC_VIP),
+        "modernChip8" => Some(Quirks::MODERN),
+        "chip48" => Some(Quirks::CHIP_48),
+        "superchip1" | "superchip" => Some(Quirks::SUPER_CHIP),
+        "xochip" => Some(Quirks::XO_CHIP),
+        _ => None,
+    }
+}
+
+// "#rrggbb"
+fn parse_colour(colour: &str) -> Option<(u8, u8, u8)> {
+    let digits = colour.strip_prefix('#')?;
+    if digits.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(digits, 16).ok()?;
+    Some(((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(dir: &Path, name: &str) -> Result<T, String> {
+    let path = dir.join(name);
+    let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn user_dir() -> Option<PathBuf> {
+    let data = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data.join("chip_8").join("chip-8-database"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::quirks::Platform;
+
+    const TWEAKED: &[u8] = &[0x00, 0xe0, 0x12, 0x00];
+    const PLAIN: &[u8] = &[0x12, 0x00];
+
+    // a database with the two ROMs above in a directory of its own
+    fn fixture(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip_8-romdb-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let programs = format!(r##"[
+            {{
+                "title": "Tweaked",
+                "authors": ["Someone", "Someone Else"],
+                "roms": {{
+                    "{}": {{
+                        "tickrate": 20,
+                        "startAddress": 768,
+                        "platforms": ["someFutureChip", "originalChip8"],
+                        "quirkyPlatforms": {{
+                            "originalChip8": {{
+                                "shift": true,
+                                "memoryLeaveIUnchanged": true,
+                                "wrap": true,
+                                "jump": true,
+                                "logic": false
+                            }},
+                            "xochip": {{ "shift": true }}
+                        }},
+                        "colors": {{ "pixels": ["#000000", "#ff8000", "orange"] }},
+                        "keys": {{ "right": 6, "up": 5, "left": 4 }}
+                    }}
+                }}
+            }},
+            {{
+                "title": "Plain",
+                "roms": {{
+                    "{}": {{ "tickrate": 0, "platforms": ["xochip"] }}
+                }}
+            }}
+        ]"##, rom_hash(TWEAKED), rom_hash(PLAIN));
+        let hashes = format!(r#"{{ "{}": 0, "{}": 1 }}"#, rom_hash(TWEAKED), rom_hash(PLAIN));
+        fs::write(dir.join("programs.json"), programs).unwrap();
+        fs::write(dir.join("sha1-hashes.json"), hashes).unwrap();
+        dir
+    }
+
+    #[test]
+    fn settings_of_a_known_rom() {
+        let dir = fixture("settings");
+        let info = lookup(TWEAKED, Some(&dir)).unwrap().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(info.title, "Tweaked");
+        assert_eq!(info.authors, ["Someone", "Someone Else"]);
+        assert_eq!(info.instructions_per_frame, Some(20));
+        assert_eq!(info.load_addr, Some(0x300));
+        assert_eq!(info.colours, [(0, 0, 0), (0xff, 0x80, 0)]);
+        let keys: Vec<(&str, u8)> = info.keys.iter().map(|(name, key)| (name.as_str(), *key)).collect();
+        assert_eq!(keys, [("left", 4), ("up", 5), ("right", 6)]);
+
+        // the first platform we know, every quirk the other way round
+        assert_eq!(info.quirks, Some(Quirks {
+            shift_vx: true,
+            load_store_increment_i: false,
+            jump_vx: true,
+            vf_reset: false,
+            clip_sprites: false,
+            ..Quirks::COSMAC_VIP
+        }));
+    }
+
+    #[test]
+    fn platform_defaults_without_tweaks() {
+        let dir = fixture("plain");
+        let info = lookup(PLAIN, Some(&dir)).unwrap().unwrap();
+        let unknown = lookup(&[0x00, 0x00], Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(info.title, "Plain");
+        assert_eq!(info.quirks, Some(Quirks::XO_CHIP));
+        assert_eq!(info.quirks.unwrap().platform, Platform::XoChip);
+        assert_eq!(info.instructions_per_frame, None);
+        assert_eq!(info.load_addr, None);
+        assert!(info.colours.is_empty() && info.keys.is_empty());
+        assert!(unknown.is_none());
+    }
+
+    #[test]
+    fn missing_database() {
+        let dir = env::temp_dir().join("chip_8-romdb-not-there");
+        let e = lookup(PLAIN, Some(&dir)).unwrap_err();
+        assert!(e.contains("sha1-hashes.json"), "{}", e);
+    }
+}
@@ -0,0 +1,311 @@
+/*
+ * Sanitizer: watches a program for things that are almost always bugs
+ * and warns about them without stopping it. Every check warns once per
+ * address it fires at, with the cycle it first fired in and how often it
+ * did.
+ *
+ * Memory ends where it does on the platform the quirks are for, see
+ * Cpu::memory_size, and so do the fonts, see Platform::big_font. Two
+ * warnings come right before the run ends, as the cpu faults either way:
+ * returning with an empty stack, and a sprite past the end of memory,
+ * 4 KiB on CHIP-8 and SUPER-CHIP. The summary is still printed after
+ * them.
+ *
+ * Code is what the disassembler finds in the ROM plus everything that
+ * ran, data is every byte an instruction read or wrote through I.
+ * Memory counts as written once the font or the ROM was loaded into it
+ * or the program stored something there.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+use crate::consts::{BIG_FONTS, BIG_FONT_ADDR, FONTS, FONT_ADDR};
+use crate::coverage::{self, Bitmap};
+use crate::cpu::Cpu;
+use crate::debug::ExecHook;
+use crate::disasm::{self, Item};
+use crate::instruction::Instruction;
+use crate::quirks::Platform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    // reads memory nothing was ever loaded into or written to
+    UninitRead,
+    // writes over code
+    SelfModify,
+    // writes into the built in fonts
+    FontWrite,
+    // 00EE with nothing on the stack
+    EmptyStackReturn,
+    // DXYN with sprite data past the end of the platform's memory
+    DrawPastEnd,
+    // runs bytes that are data, the fonts or never written
+    ExecData,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::UninitRead => "uninitialized read",
+            Check::SelfModify => "self-modifying code",
+            Check::FontWrite => "font write",
+            Check::EmptyStackReturn => "return with empty stack",
+            Check::DrawPastEnd => "sprite past end of memory",
+            Check::ExecData => "executing data",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Warning {
+    pub check: Check,
+    pub pc: u16,
+    // first time it fired
+    pub cycle: u64,
+    // the address the check is about
+    pub addr: usize,
+    pub count: u64,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:03x} cycle {}: ", self.pc, self.cycle)?;
+        match self.check {
+            Check::UninitRead => write!(f, "reads 0x{:03x}, which was never written", self.addr),
+            Check::SelfModify => write!(f, "writes 0x{:03x}, which holds code", self.addr),
+            Check::FontWrite => write!(f, "writes 0x{:03x} in the font", self.addr),
+            Check::EmptyStackReturn => write!(f, "returns with an empty stack, the run stops here"),
+            Check::DrawPastEnd => write!(f,
+                "draws a sprite that ends at 0x{:x}, past the end of memory, the run stops here",
+                self.addr),
+            Check::ExecData => write!(f, "executes 0x{:03x}, which is data", self.addr),
+        }
+    }
+}
+
+// the fonts Cpu::load_fonts puts into memory, the big one follows the small
+// one where the platform has it
+const FONT_START: usize = FONT_ADDR as usize;
+
+fn font_end(platform: Platform) -> usize {
+    if platform.big_font() {
+        BIG_FONT_ADDR as usize + BIG_FONTS.len() * BIG_FONTS[0].len()
+    } else {
+        FONT_START + FONTS.len() * FONTS[0].len()
+    }
+}
+
+pub struct Sanitizer {
+    // bytes of memory the program has
+    size: usize,
+    font_end: usize,
+    written: Bitmap,
+    code: Bitmap,
+    data: Bitmap,
+    // in the order they were found
+    warnings: Vec<Warning>,
+    seen: BTreeMap<(Check, u16), usize>,
+}
+
+impl Sanitizer {
+    // `rom` as it was loaded at `load_addr` on `platform`
+    pub fn new(rom: &[u8], load_addr: u16, platform: Platform) -> Self {
+        let size = coverage::tracked_size(platform.memory_size(), rom, load_addr);
+        let font_end = font_end(platform);
+        let mut written = Bitmap::new(size);
+        written.set(FONT_START, font_end - FONT_START);
+        written.set(load_addr as usize, rom.len());
+
+        let mut code = Bitmap::new(size);
+        for line in disasm::disassemble(rom, load_addr).lines {
+            if let Item::Code(instruction) = line.item {
+                code.set(line.addr as usize, instruction.size() as usize);
+            }
+        }
+
+        Self {
+            size,
+            font_end,
+            written,
+            code,
+            data: Bitmap::new(size),
+            warnings: Vec::new(),
+            seen: BTreeMap::new(),
+        }
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn warn(&mut self, check: Check, cpu: &Cpu, addr: usize) {
+        let pc = cpu.pc();
+        match self.seen.get(&(check, pc)) {
+            Some(&index) => self.warnings[index].count += 1,
+            None => {
+                self.seen.insert((check, pc), self.warnings.len());
+                self.warnings.push(Warning { check, pc, cycle: cpu.cycle() as u64, addr, count: 1 });
+            },
+        }
+    }
+
+    // every warning by check, with how often it fired
+    pub fn summary(&self) -> String {
+        if self.warnings.is_empty() {
+            return "Sanitizer: no problems found".to_string();
+        }
+        let mut out = String::new();
+        let _ = write!(out, "Sanitizer: {} problems at {} places",
+            self.warnings.iter().map(|w| w.count).sum::<u64>(), self.warnings.len());
+        let mut check = None;
+        for (&key, &index) in self.seen.iter() {
+            if check != Some(key.0) {
+                check = Some(key.0);
+                let _ = write!(out, "\n  {}:", key.0.name());
+            }
+            let warning = &self.warnings[index];
+            let _ = write!(out, "\n    {}", warning);
+            if warning.count > 1 {
+                let _ = write!(out, " ({} times)", warning.count);
+            }
+        }
+        out
+    }
+}
+
+impl ExecHook for Sanitizer {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        let pc = cpu.pc() as usize;
+        let size = instruction.size() as usize;
+
+        // data that was never run before, code the program changed doesn't count
+        let data = |addr: usize| self.data.get(addr) && !self.code.get(addr);
+        if let Some(addr) = (pc..pc + size).find(|&addr| !self.written.get(addr)) {
+            self.warn(Check::ExecData, cpu, addr);
+        } else if let Some(addr) = (pc..pc + size).find(|&addr| data(addr)) {
+            self.warn(Check::ExecData, cpu, addr);
+        } else if pc < self.font_end && FONT_START < pc + size {
+            self.warn(Check::ExecData, cpu, pc);
+        }
+        self.code.set(pc, size);
+
+        if *instruction == Instruction::Ret && cpu.sp() == 0 {
+            self.warn(Check::EmptyStackReturn, cpu, pc);
+        }
+
+        let access = match cpu.memory_access(instruction) {
+            Some(access) => access,
+            None => return ControlFlow::Continue(()),
+        };
+        // the part of a sprite past the end is only reported once
+        if let Instruction::Draw(..) = instruction {
+            if access.addr + access.len > self.size {
+                self.warn(Check::DrawPastEnd, cpu, access.addr + access.len);
+            }
+        }
+        if access.write {
+            if let Some(addr) = self.code.first(access.addr, access.len) {
+                self.warn(Check::SelfModify, cpu, addr);
+            }
+            if access.overlaps(FONT_START, self.font_end - FONT_START) {
+                self.warn(Check::FontWrite, cpu, access.addr);
+            }
+            self.written.set(access.addr, access.len);
+        } else if let Some(addr) = (access.addr..access.addr + access.len)
+            .find(|&addr| addr < self.size && !self.written.get(addr))
+        {
+            self.warn(Check::UninitRead, cpu, addr);
+        }
+        self.data.set(access.addr, access.len);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_asm;
+    use crate::error::Fault;
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    fn run(rom: Vec<u8>, quirks: Quirks, instructions: u32) -> (Sanitizer, Option<Fault>) {
+        let mut cpu = Cpu::new(rom.clone(), quirks).unwrap();
+        let mut sanitizer = Sanitizer::new(&rom, cpu.pc(), quirks.platform);
+        let fault = cpu.run_frame_with(Keypad::new(), instructions, &mut sanitizer).err();
+        (sanitizer, fault.map(|e| e.fault))
+    }
+
+    fn checks(sanitizer: &Sanitizer) -> Vec<Check> {
+        sanitizer.warnings().iter().map(|w| w.check).collect()
+    }
+
+    #[test]
+    fn draws_past_the_platform_memory_fault() {
+        let rom = chip8_asm!["LD I, 0xFFD", "DRW V0, V0, 5", "LD V1, 1", "end:", "JP end"];
+        let (sanitizer, fault) = run(rom.clone(), Quirks::MODERN, 10);
+        // reported right before the cpu faults on the first byte past 4 KiB
+        assert_eq!(fault, Some(Fault::MemoryOutOfBounds(0x1000)));
+        // 0xffd to 0xfff are before the end, but were never written
+        assert_eq!(checks(&sanitizer), [Check::DrawPastEnd, Check::UninitRead]);
+        assert_eq!(sanitizer.warnings()[0].addr, 0x1002);
+        assert!(sanitizer.warnings()[0].to_string().ends_with("the run stops here"));
+
+        // XO-CHIP has the whole 64 KiB
+        let (sanitizer, fault) = run(rom, Quirks::XO_CHIP, 10);
+        assert_eq!(fault, None);
+        assert_eq!(checks(&sanitizer), [Check::UninitRead]);
+    }
+
+    #[test]
+    fn draws_past_all_memory_fault() {
+        let rom = chip8_asm!["LD I, LONG 0xFFFD", "DRW V0, V0, 5"];
+        let (sanitizer, fault) = run(rom, Quirks::XO_CHIP, 10);
+        assert!(matches!(fault, Some(Fault::MemoryOutOfBounds(_))));
+        assert_eq!(checks(&sanitizer), [Check::DrawPastEnd, Check::UninitRead]);
+        assert!(sanitizer.warnings()[0].to_string().ends_with("the run stops here"));
+    }
+
+    #[test]
+    fn empty_stack_return_warns_before_the_fault() {
+        let (sanitizer, fault) = run(chip8_asm!["RET"], Quirks::MODERN, 10);
+        assert_eq!(fault, Some(Fault::StackUnderflow));
+        assert_eq!(checks(&sanitizer), [Check::EmptyStackReturn]);
+        assert_eq!(sanitizer.warnings()[0].to_string(),
+            "0x200 cycle 0: returns with an empty stack, the run stops here");
+    }
+
+    #[test]
+    fn memory_checks() {
+        let rom = chip8_asm![
+            "start:",
+            "    LD I, 0x800",
+            "    LD V0, [I]",
+            "    LD I, start",
+            "    LD [I], V0",
+            "    LD I, 0",
+            "    LD [I], V0",
+            "    JP 0x800",
+        ];
+        let (sanitizer, _) = run(rom, Quirks::MODERN, 8);
+        assert_eq!(checks(&sanitizer),
+            [Check::UninitRead, Check::SelfModify, Check::FontWrite, Check::ExecData]);
+        assert!(sanitizer.summary().starts_with("Sanitizer: 4 problems at 4 places"));
+    }
+
+    #[test]
+    fn fonts_of_the_platform() {
+        // the big font is only there from SUPER-CHIP on
+        let rom = chip8_asm!["LD I, 0x60", "LD [I], V0", "LD I, 0x48", "LD [I], V0"];
+        let (sanitizer, _) = run(rom.clone(), Quirks::COSMAC_VIP, 4);
+        assert_eq!(checks(&sanitizer), [Check::FontWrite]);
+        assert_eq!(sanitizer.warnings()[0].addr, 0x48);
+
+        let (sanitizer, _) = run(rom, Quirks::SUPER_CHIP, 4);
+        assert_eq!(checks(&sanitizer), [Check::FontWrite, Check::FontWrite]);
+        assert_eq!(sanitizer.warnings()[0].addr, 0x60);
+    }
+}
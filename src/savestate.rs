@@ -0,0 +1,349 @@
+/*
+ * Save state file format, all integers big endian:
+ *
+ * offset  size  field
+ * 0       4     magic "C8SS"
+ * 4       2     format version, currently 2
+ * 6       4     payload length in bytes
+ * 10      n     payload
+ * 10+n    4     CRC-32 (IEEE 802.3) of the payload
+ *
+ * Payload, version 2:
+ *
+ * size    field
+ * 16      V0..VF
+ * 2       I
+ * 2       PC
+ * 1       SP
+ * 32      stack, 16 x u16
+ * 1       delay timer
+ * 1       sound timer
+ * 1       flags: bit 0 waiting for key, bit 1 hi-res, bit 2 halted
+ * 1       register FX0A stores the key in, 0xff if none
+ * 1       key FX0A waits to be released, 0xff if none
+ * 1       selected XO-CHIP planes
+ * 1       XO-CHIP pitch
+ * 16      XO-CHIP audio pattern
+ * 16      RPL user flags
+ * 8       cycle counter
+ * 65536   memory
+ * 8192    framebuffer, 128 columns of 64 pixels
+ *
+ * Version 1 is the same without the FX0A key byte and is still read.
+ *
+ * Quirks and the random number generator are configuration, not machine
+ * state, and are not stored.
+ */
+
+use std::fmt;
+
+use crate::consts::*;
+
+pub const MAGIC: [u8; 4] = *b"C8SS";
+pub const VERSION: u16 = 2;
+
+const HEADER_SIZE: usize = 10;
+const CHECKSUM_SIZE: usize = 4;
+const PAYLOAD_SIZE: usize = REGISTER_COUNT + 2 + 2 + 1 + STACK_SIZE * 2 + 1 + 1 + 1 + 1
+    + 1 + 1 + 1 + AUDIO_PATTERN_SIZE + RPL_FLAGS_COUNT + 8 + RAM_SIZE
+    + HIRES_SCR_WIDTH * HIRES_SCR_HEIGHT;
+
+const FLAG_KEY_WAITING: u8 = 0x1;
+const FLAG_HIRES: u8 = 0x2;
+const FLAG_HALTED: u8 = 0x4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    ChecksumMismatch,
+    // the payload decoded to something the cpu can't be in
+    Corrupt,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state file"),
+            SaveStateError::UnsupportedVersion(v) =>
+                write!(f, "unsupported save state version {}", v),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            SaveStateError::Corrupt => write!(f, "save state is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+// Everything needed to put a Cpu back into the exact same state.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub v: [u8; REGISTER_COUNT],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; STACK_SIZE],
+    pub mem: Box<[u8; RAM_SIZE]>,
+    pub dt: u8,
+    pub st: u8,
+    pub key_waiting: bool,
+    pub key_to_store: Option<usize>,
+    pub key_held: Option<u8>,
+    pub vmem: Box<[[u8; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH]>,
+    pub hires: bool,
+    pub rpl: [u8; RPL_FLAGS_COUNT],
+    pub halted: bool,
+    pub planes: u8,
+    pub pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub pitch: u8,
+    pub cycle: u64,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(PAYLOAD_SIZE);
+        payload.extend_from_slice(&self.v);
+        payload.extend_from_slice(&self.i.to_be_bytes());
+        payload.extend_from_slice(&self.pc.to_be_bytes());
+        payload.push(self.sp);
+        for addr in self.stack.iter() {
+            payload.extend_from_slice(&addr.to_be_bytes());
+        }
+        payload.push(self.dt);
+        payload.push(self.st);
+
+        let mut flags = 0;
+        if self.key_waiting { flags |= FLAG_KEY_WAITING }
+        if self.hires { flags |= FLAG_HIRES }
+        if self.halted { flags |= FLAG_HALTED }
+        payload.push(flags);
+        payload.push(match self.key_to_store {
+            Some(x) => x as u8,
+            None => 0xff,
+        });
+        payload.push(self.key_held.unwrap_or(0xff));
+
+        payload.push(self.planes);
+        payload.push(self.pitch);
+        payload.extend_from_slice(&self.pattern);
+        payload.extend_from_slice(&self.rpl);
+        payload.extend_from_slice(&self.cycle.to_be_bytes());
+        payload.extend_from_slice(&self.mem[..]);
+        for column in self.vmem.iter() {
+            payload.extend_from_slice(column);
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len() + CHECKSUM_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_be_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes.extend_from_slice(&crc32(&payload).to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SaveStateError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(SaveStateError::Truncated);
+        }
+        if bytes[0..4] != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let payload_size = match version {
+            1 => PAYLOAD_SIZE - 1,
+            VERSION => PAYLOAD_SIZE,
+            _ => return Err(SaveStateError::UnsupportedVersion(version)),
+        };
+        let len = u32::from_be_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]) as usize;
+        if bytes.len() < HEADER_SIZE + len + CHECKSUM_SIZE {
+            return Err(SaveStateError::Truncated);
+        }
+        let payload = &bytes[HEADER_SIZE..HEADER_SIZE + len];
+        let stored = &bytes[HEADER_SIZE + len..HEADER_SIZE + len + CHECKSUM_SIZE];
+        if crc32(payload).to_be_bytes() != stored {
+            return Err(SaveStateError::ChecksumMismatch);
+        }
+        if len != payload_size {
+            return Err(SaveStateError::Corrupt);
+        }
+
+        let mut reader = Reader { bytes: payload, pos: 0 };
+
+        let mut v = [0; REGISTER_COUNT];
+        v.copy_from_slice(reader.take(REGISTER_COUNT));
+        let i = reader.u16();
+        let pc = reader.u16();
+        let sp = reader.u8();
+        let mut stack = [0; STACK_SIZE];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16();
+        }
+        let dt = reader.u8();
+        let st = reader.u8();
+        let flags = reader.u8();
+        let key_to_store = match reader.u8() {
+            0xff => None,
+            x if (x as usize) < REGISTER_COUNT => Some(x as usize),
+            _ => return Err(SaveStateError::Corrupt),
+        };
+        let key_held = match version {
+            1 => None,
+            _ => match reader.u8() {
+                0xff => None,
+                key if key < 16 => Some(key),
+                _ => return Err(SaveStateError::Corrupt),
+            },
+        };
+        let planes = reader.u8();
+        let pitch = reader.u8();
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern.copy_from_slice(reader.take(AUDIO_PATTERN_SIZE));
+        let mut rpl = [0; RPL_FLAGS_COUNT];
+        rpl.copy_from_slice(reader.take(RPL_FLAGS_COUNT));
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(reader.take(8));
+        let mut mem = Box::new([0; RAM_SIZE]);
+        mem.copy_from_slice(reader.take(RAM_SIZE));
+        let mut vmem = Box::new([[0; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH]);
+        for column in vmem.iter_mut() {
+            column.copy_from_slice(reader.take(HIRES_SCR_HEIGHT));
+        }
+
+        if sp as usize >= STACK_SIZE {
+            return Err(SaveStateError::Corrupt);
+        }
+
+        Ok(Snapshot {
+            v,
+            i,
+            pc,
+            sp,
+            stack,
+            mem,
+            dt,
+            st,
+            key_waiting: flags & FLAG_KEY_WAITING != 0,
+            key_to_store,
+            key_held,
+            vmem,
+            hires: flags & FLAG_HIRES != 0,
+            rpl,
+            halted: flags & FLAG_HALTED != 0,
+            planes,
+            pattern,
+            pitch,
+            cycle: u64::from_be_bytes(cycle),
+        })
+    }
+}
+
+// the payload length is checked up front, so reads can't run out
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        slice
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        let b = self.take(2);
+        u16::from_be_bytes([b[0], b[1]])
+    }
+}
+
+// CRC-32 as used by zip and PNG
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_asm;
+    use crate::cpu::Cpu;
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    // a cpu in the middle of a subroutine, with a sprite drawn and the
+    // timers running
+    fn snapshot() -> Snapshot {
+        let rom = chip8_asm![
+            "    LD V0, 30",
+            "    LD DT, V0",
+            "    LD I, 0",
+            "    DRW V0, V0, 5",
+            "    CALL sub",
+            "sub:",
+            "    LD V5, 0xAB",
+            "    LD V6, K",
+        ];
+        let mut cpu = Cpu::new(rom, Quirks::MODERN).unwrap();
+        cpu.run_frame(Keypad::new(), 20).unwrap();
+        assert!(cpu.waiting_for_key());
+        cpu.snapshot()
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = snapshot();
+        let bytes = snapshot.to_bytes();
+        assert_eq!(bytes[0..4], MAGIC);
+        assert_eq!(bytes.len(), HEADER_SIZE + PAYLOAD_SIZE + CHECKSUM_SIZE);
+        assert!(Snapshot::from_bytes(&bytes) == Ok(snapshot));
+    }
+
+    #[test]
+    fn restores_into_a_new_cpu() {
+        let snapshot = snapshot();
+        let mut cpu = Cpu::new(vec![0x00, 0xe0], Quirks::MODERN).unwrap();
+        cpu.restore(&Snapshot::from_bytes(&snapshot.to_bytes()).unwrap());
+        assert!(cpu.snapshot() == snapshot);
+        assert_eq!(cpu.v()[5], 0xab);
+        assert_eq!(cpu.call_stack().len(), 1);
+        assert!(cpu.waiting_for_key());
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = snapshot().to_bytes();
+
+        let mut flipped = bytes.clone();
+        flipped[HEADER_SIZE + 100] ^= 1;
+        assert!(Snapshot::from_bytes(&flipped) == Err(SaveStateError::ChecksumMismatch));
+
+        let mut crc = bytes.clone();
+        *crc.last_mut().unwrap() ^= 0x80;
+        assert!(Snapshot::from_bytes(&crc) == Err(SaveStateError::ChecksumMismatch));
+
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]) == Err(SaveStateError::Truncated));
+        assert!(Snapshot::from_bytes(&bytes[..4]) == Err(SaveStateError::Truncated));
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(Snapshot::from_bytes(&magic) == Err(SaveStateError::BadMagic));
+
+        let mut version = bytes;
+        version[5] = 9;
+        assert!(Snapshot::from_bytes(&version) == Err(SaveStateError::UnsupportedVersion(9)));
+    }
+}
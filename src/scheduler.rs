@@ -0,0 +1,147 @@
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::consts::*;
+
+// Source of time for the scheduler, swap it out to drive emulation
+// deterministically.
+pub trait Clock {
+    // monotonic time elapsed since some fixed point
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+// wall-clock time
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+// Time only moves when told to, sleeping advances it instantly.
+#[derive(Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+// Paces emulation at FRAME_RATE frames per second of `clock` time.
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    pub instructions_per_frame: u32,
+    frame_duration: Duration,
+    next_frame: Duration,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C, instructions_per_frame: u32) -> Self {
+        let next_frame = clock.now();
+        Self {
+            clock,
+            instructions_per_frame,
+            frame_duration: Duration::from_secs(1) / FRAME_RATE,
+            next_frame,
+        }
+    }
+
+    // Sleeps until the next frame is due and returns how many frames
+    // should be emulated now, more than one if the host fell behind.
+    pub fn wait_for_frame(&mut self) -> u32 {
+        let now = self.clock.now();
+        if now < self.next_frame {
+            self.clock.sleep(self.next_frame - now);
+        }
+
+        let now = self.clock.now();
+        let mut frames = 0;
+        while self.next_frame <= now {
+            self.next_frame += self.frame_duration;
+            frames += 1;
+        }
+
+        // too far behind (debugger, suspended laptop, ...), don't try
+        // to run all the missed frames at once
+        if frames > MAX_FRAME_LAG {
+            frames = 1;
+            self.next_frame = now + self.frame_duration;
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Duration {
+        Duration::from_secs(1) / FRAME_RATE
+    }
+
+    #[test]
+    fn waits_for_the_next_frame() {
+        let mut scheduler = Scheduler::new(ManualClock::new(), 10);
+        assert_eq!(scheduler.wait_for_frame(), 1);
+        assert_eq!(scheduler.clock.now(), Duration::ZERO);
+        assert_eq!(scheduler.wait_for_frame(), 1);
+        assert_eq!(scheduler.clock.now(), frame());
+        assert_eq!(scheduler.wait_for_frame(), 1);
+        assert_eq!(scheduler.clock.now(), frame() * 2);
+    }
+
+    #[test]
+    fn catches_up_when_behind() {
+        let mut scheduler = Scheduler::new(ManualClock::new(), 10);
+        scheduler.wait_for_frame();
+        scheduler.clock.advance(frame() * 3);
+        assert_eq!(scheduler.wait_for_frame(), 3);
+
+        // too far behind, start over from now
+        scheduler.clock.advance(frame() * (MAX_FRAME_LAG + 10));
+        assert_eq!(scheduler.wait_for_frame(), 1);
+        let now = scheduler.clock.now();
+        assert_eq!(scheduler.wait_for_frame(), 1);
+        assert_eq!(scheduler.clock.now(), now + frame());
+    }
+}
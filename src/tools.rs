@@ -0,0 +1,108 @@
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use chip_8::coverage::Coverage;
+use chip_8::cpu::{Cpu, InstructionOrd};
+use chip_8::debug::ExecHook;
+use chip_8::instruction::Instruction;
+use chip_8::profile::Profiler;
+use chip_8::sanitize::Sanitizer;
+use chip_8::trace::Tracer;
+
+use crate::cli::Config;
+
+/*
+ * Instrumentation the command line asked for. Every tool watches the
+ * instructions as they run and is finished when the run ends, however
+ * it ends.
+*/
+
+pub struct Tools {
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    sanitizer: Option<Sanitizer>,
+    // sanitizer warnings already printed
+    warned: usize,
+}
+
+impl Tools {
+    // `rom` is the program as it was loaded into `cpu`
+    pub fn new(cfg: &Config, cpu: &Cpu, rom: &[u8]) -> Result<Self, String> {
+        let tracer = match &cfg.trace {
+            Some(path) => Some(Tracer::create(Path::new(path),
+                    cfg.trace_format,
+                    cfg.trace_filter.clone(),
+                    cfg.trace_limit_mb * 1024 * 1024)
+                .map_err(|e| format!("can't write trace to {}: {}", path, e))?),
+            None => None,
+        };
+        let profiler = if cfg.profile.is_some() || cfg.profile_json.is_some() {
+            Some(Profiler::new(cpu.pc()))
+        } else {
+            None
+        };
+        let coverage = cfg.coverage.as_ref()
+            .map(|_| Coverage::new(rom, cpu.pc(), cpu.memory_size()));
+        let sanitizer = if cfg.sanitize {
+            Some(Sanitizer::new(rom, cpu.pc(), cpu.platform()))
+        } else {
+            None
+        };
+        Ok(Self { tracer, profiler, coverage, sanitizer, warned: 0 })
+    }
+
+    // writes out whatever the tools collected
+    pub fn finish(&mut self, cfg: &Config, cpu: &Cpu) {
+        if let (Some(tracer), Some(path)) = (self.tracer.as_mut(), &cfg.trace) {
+            match tracer.finish() {
+                Ok(()) if tracer.truncated() =>
+                    eprintln!("Trace in {} stopped at {} MB", path, cfg.trace_limit_mb),
+                Ok(()) => (),
+                Err(e) => eprintln!("Can't write trace to {}: {}", path, e),
+            }
+        }
+        if let Some(profiler) = &self.profiler {
+            let reports = [(&cfg.profile, profiler.text(cpu)), (&cfg.profile_json, profiler.json(cpu))];
+            for (path, report) in reports {
+                if let Some(path) = path {
+                    if let Err(e) = fs::write(path, report) {
+                        eprintln!("Can't write profile to {}: {}", path, e);
+                    }
+                }
+            }
+        }
+        if let (Some(coverage), Some(path)) = (&self.coverage, &cfg.coverage) {
+            if let Err(e) = fs::write(path, coverage.report()) {
+                eprintln!("Can't write coverage to {}: {}", path, e);
+            }
+            println!("{}", coverage.summary());
+        }
+        if let Some(sanitizer) = &self.sanitizer {
+            eprintln!("{}", sanitizer.summary());
+        }
+    }
+}
+
+impl ExecHook for Tools {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        self.tracer.before(cpu, instruction)?;
+        self.profiler.before(cpu, instruction)?;
+        self.coverage.before(cpu, instruction)?;
+        if let Some(sanitizer) = self.sanitizer.as_mut() {
+            sanitizer.before(cpu, instruction)?;
+            for warning in &sanitizer.warnings()[self.warned..] {
+                eprintln!("Sanitizer: {}", warning);
+            }
+            self.warned = sanitizer.warnings().len();
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn after(&mut self, cpu: &Cpu, instruction: &Instruction, ord: InstructionOrd) {
+        self.tracer.after(cpu, instruction, ord);
+        self.profiler.after(cpu, instruction, ord);
+        self.coverage.after(cpu, instruction, ord);
+    }
+}
@@ -0,0 +1,310 @@
+/*
+ * Instruction trace, one record per executed instruction, for diffing
+ * runs between builds.
+ *
+ * The text format starts with a "#" header line, then one line per
+ * instruction:
+ *
+ *     CYCLE PC OPCODE MNEMONIC  V0-VF I -> V0-VF I
+ *
+ * with the registers before and after the instruction as hex. CYCLE
+ * counts ticks, the ones spent waiting in FX0A included.
+ *
+ * The binary format starts with the 8 bytes "C8TRACE1", followed by 50
+ * byte records, all numbers little endian:
+ *
+ *     u64 cycle, u16 pc, u16 opcode, u16 second word of F000 NNNN or 0,
+ *     u8[16] V before, u16 I before, u8[16] V after, u16 I after
+ *
+ * Tracing stops at the first record that would take the file past the
+ * size limit.
+ */
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use crate::consts::REGISTER_COUNT;
+use crate::cpu::{Cpu, InstructionOrd};
+use crate::debug::ExecHook;
+use crate::instruction::{Instruction, OpClass};
+
+const MAGIC: &[u8; 8] = b"C8TRACE1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(TraceFormat::Text),
+            "binary" | "bin" => Ok(TraceFormat::Binary),
+            _ => Err(format!("unknown trace format: {}", s)),
+        }
+    }
+}
+
+// which instructions get traced, everything by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    // inclusive range of instruction addresses
+    pub range: Option<(u16, u16)>,
+    // empty for all classes
+    pub classes: Vec<OpClass>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, instruction: &Instruction) -> bool {
+        if let Some((start, end)) = self.range {
+            if pc < start || pc > end {
+                return false;
+            }
+        }
+        self.classes.is_empty() || self.classes.contains(&instruction.class())
+    }
+}
+
+// registers the traced instruction started with
+struct Before {
+    cycle: u64,
+    pc: u16,
+    v: [u8; REGISTER_COUNT],
+    i: u16,
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    limit: u64,
+    written: u64,
+    before: Option<Before>,
+    truncated: bool,
+    // first write error, tracing stops there
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    // traces into `out` until `limit` bytes were written
+    pub fn new(mut out: Box<dyn Write>, format: TraceFormat, filter: TraceFilter, limit: u64)
+        -> io::Result<Self>
+    {
+        let header: Vec<u8> = match format {
+            TraceFormat::Text => b"# cycle pc opcode mnemonic v0-vf i -> v0-vf i\n".to_vec(),
+            TraceFormat::Binary => MAGIC.to_vec(),
+        };
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            format,
+            filter,
+            limit,
+            written: header.len() as u64,
+            before: None,
+            truncated: false,
+            error: None,
+        })
+    }
+
+    pub fn create(path: &Path, format: TraceFormat, filter: TraceFilter, limit: u64)
+        -> io::Result<Self>
+    {
+        let file = File::create(path)?;
+        Tracer::new(Box::new(BufWriter::new(file)), format, filter, limit)
+    }
+
+    // true once the size limit cut the trace short
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    // flushes the trace, with the first error writing it if there was one
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+
+    fn record(&self, before: &Before, cpu: &Cpu, instruction: &Instruction) -> Vec<u8> {
+        match self.format {
+            TraceFormat::Text => format!("{} {:03x} {:04x} {:<20} {} {:03x} -> {} {:03x}\n",
+                before.cycle, before.pc, instruction.encode(), instruction.to_string(),
+                hex(&before.v), before.i, hex(cpu.v()), cpu.i()).into_bytes(),
+            TraceFormat::Binary => {
+                let operand = match instruction {
+                    Instruction::LoadILong(nnnn) => *nnnn,
+                    _ => 0,
+                };
+                let mut record = Vec::with_capacity(50);
+                record.extend_from_slice(&before.cycle.to_le_bytes());
+                record.extend_from_slice(&before.pc.to_le_bytes());
+                record.extend_from_slice(&instruction.encode().to_le_bytes());
+                record.extend_from_slice(&operand.to_le_bytes());
+                record.extend_from_slice(&before.v);
+                record.extend_from_slice(&before.i.to_le_bytes());
+                record.extend_from_slice(cpu.v());
+                record.extend_from_slice(&cpu.i().to_le_bytes());
+                record
+            },
+        }
+    }
+}
+
+impl ExecHook for Tracer {
+    fn before(&mut self, cpu: &Cpu, instruction: &Instruction) -> ControlFlow<()> {
+        self.before = if self.filter.matches(cpu.pc(), instruction) {
+            Some(Before {
+                cycle: cpu.cycle() as u64,
+                pc: cpu.pc(),
+                v: *cpu.v(),
+                i: cpu.i(),
+            })
+        } else {
+            None
+        };
+        ControlFlow::Continue(())
+    }
+
+    fn after(&mut self, cpu: &Cpu, instruction: &Instruction, _ord: InstructionOrd) {
+        let before = match self.before.take() {
+            Some(before) if !self.truncated && self.error.is_none() => before,
+            _ => return,
+        };
+        let record = self.record(&before, cpu, instruction);
+        if self.written + record.len() as u64 > self.limit {
+            self.truncated = true;
+            return;
+        }
+        match self.out.write_all(&record) {
+            Ok(()) => self.written += record.len() as u64,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+    use crate::chip8_asm;
+    use crate::keypad::Keypad;
+    use crate::quirks::Quirks;
+
+    // output that can still be read once the tracer has it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn program() -> Cpu {
+        let rom = chip8_asm!["LD V0, 0x12", "LD I, LONG 0x1234", "ADD V0, 1", "loop:", "JP loop"];
+        Cpu::new(rom, Quirks::XO_CHIP).unwrap()
+    }
+
+    // what tracing `ticks` instructions writes
+    fn trace(format: TraceFormat, filter: TraceFilter, ticks: usize) -> Vec<u8> {
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()), format, filter, u64::MAX).unwrap();
+        let mut cpu = program();
+        for _ in 0..ticks {
+            assert!(cpu.tick_with(Keypad::new(), &mut tracer).unwrap().is_continue());
+        }
+        tracer.finish().unwrap();
+        let bytes = out.0.borrow().clone();
+        bytes
+    }
+
+    #[test]
+    fn text_lines() {
+        let text = String::from_utf8(trace(TraceFormat::Text, TraceFilter::default(), 3)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let zeros = "00".repeat(REGISTER_COUNT - 1);
+        assert_eq!(lines, [
+            "# cycle pc opcode mnemonic v0-vf i -> v0-vf i".to_string(),
+            format!("0 200 6012 LD V0, 0x12          00{} 000 -> 12{} 000", zeros, zeros),
+            format!("1 202 f000 LD I, LONG 0x1234    12{} 000 -> 12{} 1234", zeros, zeros),
+            format!("2 206 7001 ADD V0, 0x01         12{} 1234 -> 13{} 1234", zeros, zeros),
+        ]);
+    }
+
+    #[test]
+    fn binary_records() {
+        let bytes = trace(TraceFormat::Binary, TraceFilter::default(), 3);
+        assert_eq!(bytes.len(), MAGIC.len() + 3 * 50);
+        assert_eq!(&bytes[..8], MAGIC);
+
+        let record = &bytes[8 + 50..8 + 100];
+        assert_eq!(record[..8], 1u64.to_le_bytes());
+        assert_eq!(record[8..10], 0x202u16.to_le_bytes());
+        assert_eq!(record[10..12], 0xf000u16.to_le_bytes());
+        assert_eq!(record[12..14], 0x1234u16.to_le_bytes());
+        assert_eq!(record[14], 0x12);
+        assert_eq!(record[30..32], [0, 0]);
+        assert_eq!(record[32], 0x12);
+        assert_eq!(record[48..50], 0x1234u16.to_le_bytes());
+
+        // other instructions have no second word
+        let record = &bytes[8 + 100..];
+        assert_eq!(record[10..14], [0x01, 0x70, 0, 0]);
+    }
+
+    #[test]
+    fn filters() {
+        let pcs = |filter| {
+            let bytes = trace(TraceFormat::Binary, filter, 5);
+            bytes[8..].chunks(50).map(|r| u16::from_le_bytes([r[8], r[9]])).collect::<Vec<_>>()
+        };
+        assert_eq!(pcs(TraceFilter::default()), [0x200, 0x202, 0x206, 0x208, 0x208]);
+        assert_eq!(pcs(TraceFilter { range: Some((0x202, 0x206)), classes: vec![] }), [0x202, 0x206]);
+        assert_eq!(pcs(TraceFilter { range: None, classes: vec![OpClass::Alu] }), [0x200, 0x206]);
+        assert_eq!(pcs(TraceFilter {
+            range: Some((0x204, 0xfff)),
+            classes: vec![OpClass::Alu, OpClass::Flow],
+        }), [0x206, 0x208, 0x208]);
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        let path = std::env::temp_dir().join(format!("chip_8-trace-{}", std::process::id()));
+        let limit = (MAGIC.len() + 2 * 50 + 49) as u64;
+        let mut tracer = Tracer::create(&path, TraceFormat::Binary, TraceFilter::default(), limit)
+            .unwrap();
+        let mut cpu = program();
+        for _ in 0..2 {
+            assert!(cpu.tick_with(Keypad::new(), &mut tracer).unwrap().is_continue());
+        }
+        assert!(!tracer.truncated());
+        for _ in 0..3 {
+            assert!(cpu.tick_with(Keypad::new(), &mut tracer).unwrap().is_continue());
+        }
+        assert!(tracer.truncated());
+        tracer.finish().unwrap();
+
+        let size = fs::metadata(&path).unwrap().len();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(size, (MAGIC.len() + 2 * 50) as u64);
+        assert!(size <= limit);
+    }
+}
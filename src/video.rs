@@ -1,15 +1,21 @@
-use sdl2;
 use sdl2::pixels::Color;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 use sdl2::rect::Rect;
 
-use crate::consts::*;
+use chip_8::consts::*;
+use chip_8::frontend::DisplaySink;
 
 pub struct Video {
-    bg_color: Color,
-    fg_color: Color, 
+    // indexed by the plane bitmask of a pixel: background, plane 1,
+    // plane 2 and both planes
+    palette: [Color; 4],
     canvas: Canvas<Window>,
+    // size of a hi-res pixel, the largest that fits the window, lo-res
+    // pixels are twice that
+    scale: u32,
+    // top left corner of the picture, centred in the window
+    offset: (i32, i32),
 }
 
 impl Video {
@@ -17,53 +23,69 @@ impl Video {
                width: u32,
                height: u32,
                title: &str,
-               bg: (u8, u8, u8),
-               fg: (u8, u8, u8)) -> Self 
+               palette: [(u8, u8, u8); 4],
+               fullscreen: bool) -> Self 
     {
-        let window = ctx
-            .video()
-            .unwrap()
-            .window(&title, width, height)
-            .build()
-            .unwrap();
+        let palette = palette.map(|(r, g, b)| Color::RGB(r, g, b));
+
+        let video = ctx.video().unwrap();
+        let mut window = video.window(title, width, height);
+        if fullscreen {
+            window.fullscreen_desktop();
+        }
+        let window = window.build().unwrap();
 
         let mut canvas = window.into_canvas().build().unwrap();
-        canvas.set_draw_color(Color::RGB(bg.0, bg.1, bg.2));
+        canvas.set_draw_color(palette[0]);
         canvas.clear();
         canvas.present();
+
+        // fullscreen windows get the size of the screen, not the asked for one
+        let (width, height) = canvas.output_size().unwrap_or((width, height));
+        let scale = (width / HIRES_SCR_WIDTH as u32).min(height / HIRES_SCR_HEIGHT as u32).max(1);
+        let offset = (
+            (width as i32 - (HIRES_SCR_WIDTH as u32 * scale) as i32) / 2,
+            (height as i32 - (HIRES_SCR_HEIGHT as u32 * scale) as i32) / 2,
+        );
         Self { 
             canvas,
-            bg_color: Color::RGB(bg.0, bg.1, bg.2),
-            fg_color: Color::RGB(fg.0, fg.1, fg.2),
+            palette,
+            scale,
+            offset,
         }
     }
     
+    // draws the top left `cols`x`rows` corner of the framebuffer, so the
+    // same window fits both lo-res and hi-res frames given a matching scale
     pub fn render_sprite_new(&mut self,
-        sprite: &[[u8; SCR_HEIGHT]; SCR_WIDTH],
-        width: u32,
-        height: u32,
+        sprite: &[[u8; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH],
+        cols: usize,
+        rows: usize,
         scale: u32) -> Result<(), String>
     {
-        for i in 0..SCR_HEIGHT {
-            for j in 0..SCR_WIDTH {
-                if sprite[j][i] == 1 {
-                    self.canvas.set_draw_color(self.fg_color);
-                    self.canvas.fill_rect(Rect::new(
-                            j as i32*scale as i32,
-                            i as i32*scale as i32,
-                            width*scale,
-                            height*scale))?;
-                } else {
-                    self.canvas.set_draw_color(self.bg_color);
-                    self.canvas.fill_rect(Rect::new(
-                            j as i32*scale as i32,
-                            i as i32*scale as i32,
-                            width*scale,
-                            height*scale))?;
-                }
+        for (j, column) in sprite.iter().enumerate().take(cols) {
+            for (i, pixel) in column.iter().enumerate().take(rows) {
+                self.canvas.set_draw_color(self.palette[*pixel as usize & 0x3]);
+                self.canvas.fill_rect(Rect::new(
+                        self.offset.0 + j as i32*scale as i32,
+                        self.offset.1 + i as i32*scale as i32,
+                        scale,
+                        scale))?;
             }
         }
         self.canvas.present();
         Ok(())
     }
 }
+
+impl DisplaySink for Video {
+    fn draw(&mut self,
+            vmem: &[[u8; HIRES_SCR_HEIGHT]; HIRES_SCR_WIDTH],
+            cols: usize,
+            rows: usize) -> Result<(), String>
+    {
+        // lo-res pixels are drawn at twice the size to fill the same window
+        let scale = self.scale * (HIRES_SCR_WIDTH / cols) as u32;
+        self.render_sprite_new(vmem, cols, rows, scale)
+    }
+}